@@ -0,0 +1,22 @@
+use std::{env, fs};
+
+use rnix::serialize::SerializedParse;
+
+fn main() {
+    let mut iter = env::args().skip(1).peekable();
+    if iter.peek().is_none() {
+        eprintln!("Usage: dump-json <file>");
+        return;
+    }
+    for file in iter {
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("error reading file: {}", err);
+                return;
+            }
+        };
+        let parse = rnix::Root::parse(&content);
+        println!("{}", serde_json::to_string(&SerializedParse::from(&parse)).unwrap());
+    }
+}
@@ -1,4 +1,3 @@
-use rnix::parser::ParseError;
 use std::{env, fs};
 
 fn main() {
@@ -17,26 +16,24 @@ fn main() {
         }
     };
     let ast = rnix::Root::parse(&content);
-    for error in ast.errors() {
-        let range = match error {
-            ParseError::Unexpected(range) => range,
-            ParseError::UnexpectedExtra(range) => range,
-            ParseError::UnexpectedWanted(_, range, _) => range,
-            ParseError::UnexpectedDoubleBind(range) => range,
-            ParseError::DuplicatedArgs(range, _) => range,
-            err => {
-                eprintln!("error: {}", err);
-                continue;
-            }
-        };
-        eprintln!("----- ERROR -----");
-        eprintln!("{}", error);
+    for diagnostic in ast.diagnostics() {
+        let range = diagnostic.range;
+        eprintln!("----- {} -----", diagnostic.severity.to_string().to_uppercase());
+        eprintln!("{}", diagnostic);
+        for label in &diagnostic.labels {
+            eprintln!(
+                "note at {}..{}: {}",
+                usize::from(label.range.start()),
+                usize::from(label.range.end()),
+                label.message
+            );
+        }
         let start = usize::from(range.start());
-        let start_row = content[..start].lines().count() - 1;
+        let start_row = content[..start].matches('\n').count();
         let start_line = content[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
         let start_col = content[start_line..start].chars().count();
         let end = usize::from(range.end());
-        let end_row = content[..end].lines().count() - 1;
+        let end_row = content[..end].matches('\n').count();
         let end_line = content[..end].rfind('\n').map(|i| i + 1).unwrap_or(0);
         let end_col = content[end_line..end].chars().count();
 
@@ -1,12 +1,19 @@
 //! Provides a type system for the AST, in some sense
 
+mod attr_util;
+mod edit;
 mod expr_ext;
 mod interpol;
+pub mod make;
 mod nodes;
 mod operators;
 mod path_util;
+mod ptr;
 mod str_util;
+mod structural;
+mod template;
 mod tokens;
+mod visit;
 
 use crate::{NixLanguage, SyntaxKind, SyntaxToken};
 
@@ -14,7 +21,12 @@ pub use expr_ext::LiteralKind;
 pub use interpol::*;
 pub use nodes::*;
 pub use operators::{BinOpKind, UnaryOpKind};
+pub(crate) use path_util::resolve_prefix;
+pub use path_util::PathKind;
+pub use ptr::AstPtr;
+pub use template::{template, Splice, TemplateError};
 pub use tokens::*;
+pub use visit::*;
 
 pub trait AstNode: rowan::ast::AstNode<Language = NixLanguage> {}
 
@@ -49,7 +61,7 @@ mod support {
     }
 
     pub(super) fn token<N: AstNode, T: AstToken>(parent: &N) -> Option<T> {
-        children_tokens(parent).nth(0)
+        children_tokens(parent).next()
     }
 
     /// Token untyped
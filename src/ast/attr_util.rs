@@ -0,0 +1,122 @@
+use crate::ast::{self, InterpolPart};
+
+impl ast::Attr {
+    /// The name of the attribute, if it doesn't depend on an interpolation.
+    /// This is the case for identifiers, strings without interpolations and
+    /// dynamic attributes of such strings, like `${"foo"}`.
+    pub fn static_name(&self) -> Option<String> {
+        match self {
+            ast::Attr::Ident(ident) => Some(ident.ident_token()?.text().to_string()),
+            ast::Attr::Str(s) => static_str(s),
+            ast::Attr::Dynamic(dynamic) => {
+                let mut expr = dynamic.expr()?;
+                while let ast::Expr::Paren(paren) = expr {
+                    expr = paren.expr()?;
+                }
+                match expr {
+                    ast::Expr::Str(s) => static_str(&s),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// An attribute called `name`, which is quoted if it isn't a valid identifier
+    pub fn from_name(name: &str) -> ast::Attr {
+        let text = if is_valid_ident(name) { name.to_string() } else { quote(name) };
+        ast::Attrpath::parse(&text).tree().attrs().next().unwrap()
+    }
+}
+
+impl ast::Attrpath {
+    /// The names of all attributes in the path, if none of them depend on
+    /// an interpolation
+    pub fn static_path(&self) -> Option<Vec<String>> {
+        self.attrs().map(|attr| attr.static_name()).collect()
+    }
+}
+
+fn static_str(s: &ast::Str) -> Option<String> {
+    s.normalized_parts()
+        .into_iter()
+        .map(|part| match part {
+            InterpolPart::Literal(literal) => Some(literal),
+            InterpolPart::Interpolation(_) => None,
+        })
+        .collect()
+}
+
+/// Returns true if `name` can be used as an attribute without quotes. Unlike
+/// other keywords, `or` is allowed.
+fn is_valid_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'));
+    valid
+        && !matches!(
+            name,
+            "assert" | "else" | "if" | "in" | "inherit" | "let" | "rec" | "then" | "with"
+        )
+}
+
+/// Write `s` as a string literal
+pub(super) fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::ast::AstNode;
+
+    use crate::ast;
+
+    fn static_path(s: &str) -> Option<Vec<String>> {
+        let parse = ast::Attrpath::parse(s);
+        assert!(parse.errors().is_empty());
+        parse.tree().static_path()
+    }
+
+    #[test]
+    fn static_names() {
+        assert_eq!(
+            static_path(r#"a."b c".${"d"}.${("e")}.or"#),
+            Some(vec!["a".into(), "b c".into(), "d".into(), "e".into(), "or".into()])
+        );
+        assert_eq!(static_path(r#""a\nb""#), Some(vec!["a\nb".into()]));
+        assert_eq!(static_path(r#"a."${b}""#), None);
+        assert_eq!(static_path("a.${b}"), None);
+        assert_eq!(static_path(r#"${"a${b}"}"#), None);
+    }
+
+    #[test]
+    fn from_name() {
+        for (name, text) in [
+            ("foo", "foo"),
+            ("foo-bar'", "foo-bar'"),
+            ("or", "or"),
+            ("if", r#""if""#),
+            ("1a", r#""1a""#),
+            ("", r#""""#),
+            ("a.b", r#""a.b""#),
+            ("\"${x}\\\n$", r#""\"\${x}\\\n$""#),
+        ] {
+            let attr = ast::Attr::from_name(name);
+            assert_eq!(attr.syntax().to_string(), text);
+            assert_eq!(attr.static_name().as_deref(), Some(name));
+        }
+    }
+}
@@ -0,0 +1,438 @@
+//! Methods to edit a tree in place.
+//!
+//! They only work on mutable trees, as returned by
+//! [`SyntaxNode::clone_for_update`](rowan::SyntaxNode::clone_for_update), and
+//! panic otherwise. Inserted nodes are copied, so they can come from any tree,
+//! and are indented like their new neighbours.
+
+use rowan::{ast::AstNode, GreenNode, GreenToken, Language, NodeOrToken};
+
+use crate::{
+    ast::{self, HasEntry},
+    NixLanguage, SyntaxElement, SyntaxKind,
+    SyntaxKind::*,
+    SyntaxNode, SyntaxToken,
+};
+
+impl ast::AttrSet {
+    /// Add `entry` after the last entry of the set. Bindings without braces,
+    /// as returned by [`AttrSet::parse_bindings`](ast::AttrSet::parse_bindings),
+    /// get it at the start if they are empty
+    pub fn add_entry(&self, entry: ast::Entry) {
+        let last = self.entries().last().map(|e| e.syntax().clone());
+        let open = self.l_curly_token().map(Into::into);
+        add_item(self.syntax(), last, open, entry.syntax());
+    }
+
+    /// Remove `entry` from the set, together with the whitespace before it
+    /// and a comment that follows it on the same line
+    pub fn remove_entry(&self, entry: &ast::Entry) {
+        if entry.syntax().parent().as_ref() == Some(self.syntax()) {
+            remove_item(entry.syntax());
+        }
+    }
+}
+
+impl ast::LetIn {
+    /// Add `entry` after the last binding of the `let`
+    pub fn add_binding(&self, entry: ast::Entry) {
+        let last = self.entries().last().map(|e| e.syntax().clone());
+        let open = self.let_token().map(Into::into);
+        add_item(self.syntax(), last, open, entry.syntax());
+    }
+}
+
+impl ast::AttrpathValue {
+    /// Replace the value of the binding with `value`
+    pub fn set_value(&self, value: ast::Expr) {
+        let new = detached(value.syntax());
+        reindent(&new, &line_indent(self.syntax()));
+        match self.value() {
+            Some(old) => {
+                let index = old.syntax().index();
+                self.syntax().splice_children(index..index + 1, vec![new.into()]);
+            }
+            None => {
+                let Some(assign) = self.assign_token() else { return };
+                let elements = vec![whitespace(" ").into(), new.into()];
+                insert_after(self.syntax(), &assign.into(), elements);
+            }
+        }
+    }
+}
+
+impl ast::List {
+    /// Add `item` at the end of the list
+    pub fn push_item(&self, item: ast::Expr) {
+        let last = self.items().last().map(|e| e.syntax().clone());
+        let open = self.l_brack_token().map(Into::into);
+        add_item(self.syntax(), last, open, item.syntax());
+    }
+
+    /// Insert `item` at position `index`, shifting the following items.
+    /// Panics if `index` is greater than the number of items.
+    pub fn insert_item(&self, index: usize, item: ast::Expr) {
+        let items: Vec<_> = self.items().collect();
+        assert!(index <= items.len(), "index {index} out of bounds for {} items", items.len());
+        let Some(next) = items.get(index) else { return self.push_item(item) };
+        let next = next.syntax();
+        let new = detached(item.syntax());
+        let separator = match next.prev_sibling_or_token() {
+            Some(NodeOrToken::Token(ws)) if ws.kind() == TOKEN_WHITESPACE => ws.text().to_string(),
+            _ => " ".to_string(),
+        };
+        if let Some(indent) = separator.rsplit_once('\n').map(|(_, indent)| indent) {
+            reindent(&new, indent);
+        }
+        let index = next.index();
+        self.syntax()
+            .splice_children(index..index, vec![new.into(), whitespace(&separator).into()]);
+    }
+
+    /// Remove the item at position `index`. Panics if there is no such item.
+    pub fn remove_item(&self, index: usize) {
+        let items: Vec<_> = self.items().collect();
+        assert!(index < items.len(), "index {index} out of bounds for {} items", items.len());
+        remove_item(items[index].syntax());
+    }
+}
+
+impl ast::Pattern {
+    /// Add `entry` after the last entry of the pattern, using the same comma
+    /// style as the existing entries
+    pub fn add_entry(&self, entry: ast::PatEntry) {
+        let entries: Vec<_> = self.pat_entries().collect();
+        let new = detached(entry.syntax());
+        let Some(last) = entries.last().map(|e| e.syntax().clone()) else {
+            let Some(open) =
+                self.syntax().children_with_tokens().find(|e| e.kind() == TOKEN_L_BRACE)
+            else {
+                return;
+            };
+            let next = open.next_sibling_or_token();
+            let mut elements = vec![whitespace(" ").into(), new.into()];
+            if self.ellipsis_token().is_some() {
+                elements.push(token(TOKEN_COMMA, ",").into());
+            }
+            if next.as_ref().is_none_or(|e| e.kind() != TOKEN_WHITESPACE) {
+                elements.push(whitespace(" ").into());
+            }
+            return insert_after(self.syntax(), &open, elements);
+        };
+
+        // Copy the commas and whitespace between the last two entries, or
+        // between the last entry and the ellipsis
+        let separator: Vec<SyntaxToken> = if entries.len() >= 2 {
+            let prev = entries[entries.len() - 2].syntax();
+            separator_tokens(prev.next_sibling_or_token(), |e| e.as_node() == Some(&last))
+        } else if self.ellipsis_token().is_some() {
+            separator_tokens(last.next_sibling_or_token(), |e| e.kind() == TOKEN_ELLIPSIS)
+        } else {
+            vec![]
+        };
+        let separator = if separator.iter().any(|t| t.kind() == TOKEN_COMMA) {
+            separator
+        } else {
+            vec![token(TOKEN_COMMA, ","), whitespace(" ")]
+        };
+
+        if let Some((_, indent)) = separator
+            .iter()
+            .filter(|t| t.kind() == TOKEN_WHITESPACE)
+            .find_map(|t| t.text().rsplit_once('\n'))
+        {
+            reindent(&new, indent);
+        }
+        let mut elements: Vec<SyntaxElement> = separator.into_iter().map(Into::into).collect();
+        elements.push(new.into());
+        insert_after(self.syntax(), &last.into(), elements);
+    }
+}
+
+/// Collect fresh copies of the comma and whitespace tokens from `start` until
+/// `end` matches
+fn separator_tokens(
+    start: Option<SyntaxElement>,
+    end: impl Fn(&SyntaxElement) -> bool,
+) -> Vec<SyntaxToken> {
+    std::iter::successors(start, |e| e.next_sibling_or_token())
+        .take_while(|e| !end(e))
+        .filter_map(|e| e.into_token())
+        .filter(|t| matches!(t.kind(), TOKEN_COMMA | TOKEN_WHITESPACE))
+        .map(|t| token(t.kind(), t.text()))
+        .collect()
+}
+
+/// Insert a copy of `item` into `parent` after `last`, or right after `open`
+/// if there are no items yet, or as the first child if there is no `open`
+fn add_item(
+    parent: &SyntaxNode,
+    last: Option<SyntaxNode>,
+    open: Option<SyntaxElement>,
+    item: &SyntaxNode,
+) {
+    let new = detached(item);
+    match last {
+        Some(last) => {
+            let separator = match last.prev_sibling_or_token() {
+                Some(NodeOrToken::Token(ws)) if ws.kind() == TOKEN_WHITESPACE => {
+                    ws.text().to_string()
+                }
+                _ => " ".to_string(),
+            };
+            if let Some((_, indent)) = separator.rsplit_once('\n') {
+                reindent(&new, indent);
+            }
+            let anchor = end_of_line(last.into());
+            insert_after(parent, &anchor, vec![whitespace(&separator).into(), new.into()]);
+        }
+        None => {
+            let Some(open) = open else {
+                parent.splice_children(0..0, vec![new.into()]);
+                return;
+            };
+            let next = open.next_sibling_or_token();
+            let multiline = matches!(
+                &next,
+                Some(NodeOrToken::Token(ws)) if ws.kind() == TOKEN_WHITESPACE && ws.text().contains('\n')
+            );
+            if multiline {
+                let indent = format!("{}  ", line_indent_of(&open));
+                reindent(&new, &indent);
+                let anchor = end_of_line(open);
+                let elements = vec![whitespace(&format!("\n{indent}")).into(), new.into()];
+                insert_after(parent, &anchor, elements);
+            } else {
+                let mut elements = vec![whitespace(" ").into(), new.into()];
+                if next.as_ref().is_none_or(|e| e.kind() != TOKEN_WHITESPACE) {
+                    elements.push(whitespace(" ").into());
+                }
+                insert_after(parent, &open, elements);
+            }
+        }
+    }
+}
+
+/// Detach `node` along with the whitespace before it and a comment following
+/// it on the same line
+fn remove_item(node: &SyntaxNode) {
+    let mut elements = vec![];
+    if let Some(NodeOrToken::Token(ws)) = node.prev_sibling_or_token() {
+        if ws.kind() == TOKEN_WHITESPACE {
+            elements.push(ws.into());
+        }
+    }
+    let start = SyntaxElement::from(node.clone());
+    let end = end_of_line(start.clone());
+    let mut current = Some(start);
+    while let Some(element) = current {
+        current = element.next_sibling_or_token().filter(|_| element != end);
+        elements.push(element);
+    }
+    for element in elements {
+        element.detach();
+    }
+}
+
+/// The last element on the same line as `element`, skipping over comments
+/// that follow it
+fn end_of_line(element: SyntaxElement) -> SyntaxElement {
+    let mut current = element;
+    loop {
+        match current.next_sibling_or_token() {
+            Some(next) if next.kind() == TOKEN_COMMENT => current = next,
+            Some(NodeOrToken::Token(ws))
+                if ws.kind() == TOKEN_WHITESPACE && !ws.text().contains('\n') =>
+            {
+                match ws.next_sibling_or_token() {
+                    Some(comment) if comment.kind() == TOKEN_COMMENT => current = comment,
+                    _ => return current,
+                }
+            }
+            _ => return current,
+        }
+    }
+}
+
+fn insert_after(parent: &SyntaxNode, anchor: &SyntaxElement, elements: Vec<SyntaxElement>) {
+    let index = anchor.index() + 1;
+    parent.splice_children(index..index, elements);
+}
+
+/// The indentation of the line on which `node` starts
+fn line_indent(node: &SyntaxNode) -> String {
+    node.first_token().map(|t| line_indent_of(&t.into())).unwrap_or_default()
+}
+
+fn line_indent_of(element: &SyntaxElement) -> String {
+    let first = match element {
+        NodeOrToken::Node(node) => node.first_token(),
+        NodeOrToken::Token(token) => Some(token.clone()),
+    };
+    std::iter::successors(first.and_then(|t| t.prev_token()), |t| t.prev_token())
+        .find(|t| t.text().contains('\n'))
+        .filter(|t| t.kind() == TOKEN_WHITESPACE)
+        .and_then(|t| t.text().rsplit_once('\n').map(|(_, indent)| indent.to_string()))
+        .unwrap_or_default()
+}
+
+/// Add `indent` after every line break in the whitespace of `node`
+fn reindent(node: &SyntaxNode, indent: &str) {
+    if indent.is_empty() {
+        return;
+    }
+    let tokens: Vec<_> = node
+        .descendants_with_tokens()
+        .filter_map(|e| e.into_token())
+        .filter(|t| t.kind() == TOKEN_WHITESPACE && t.text().contains('\n'))
+        .collect();
+    for old in tokens {
+        let new = whitespace(&old.text().replace('\n', &format!("\n{indent}")));
+        let (Some(parent), index) = (old.parent(), old.index()) else { continue };
+        parent.splice_children(index..index + 1, vec![new.into()]);
+    }
+}
+
+/// A mutable copy of `node` that isn't attached to any tree
+fn detached(node: &SyntaxNode) -> SyntaxNode {
+    node.clone_subtree().clone_for_update()
+}
+
+fn whitespace(text: &str) -> SyntaxToken {
+    token(TOKEN_WHITESPACE, text)
+}
+
+/// A mutable token that isn't attached to any tree
+fn token(kind: SyntaxKind, text: &str) -> SyntaxToken {
+    let token = GreenToken::new(NixLanguage::kind_to_raw(kind), text);
+    let root = GreenNode::new(NixLanguage::kind_to_raw(NODE_ROOT), [token.into()]);
+    let token = SyntaxNode::new_root(root).clone_for_update().first_token().unwrap();
+    token.detach();
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::ast::AstNode;
+
+    use crate::ast::{self, HasEntry};
+
+    fn tree<N: AstNode<Language = crate::NixLanguage>>(src: &str) -> N {
+        let root = crate::Root::parse(src).ok().unwrap();
+        root.syntax().clone_for_update().descendants().find_map(N::cast).unwrap()
+    }
+
+    fn entry(src: &str) -> ast::Entry {
+        tree(src)
+    }
+
+    fn expr(src: &str) -> ast::Expr {
+        crate::Root::parse(src).ok().unwrap().expr().unwrap()
+    }
+
+    #[test]
+    fn attr_set() {
+        let set: ast::AttrSet = tree("{ a = 1; }");
+        set.add_entry(entry("{ b = 2; }"));
+        assert_eq!(set.to_string(), "{ a = 1; b = 2; }");
+
+        let set: ast::AttrSet = tree("{}");
+        set.add_entry(entry("{ inherit a; }"));
+        assert_eq!(set.to_string(), "{ inherit a; }");
+
+        let set: ast::AttrSet = tree("{\n  x = {\n    a = 1; # one\n  };\n}");
+        let inner: ast::AttrSet = tree("{ b = {\n  c = 3;\n}; }");
+        let inner = inner.entries().next().unwrap();
+        let nested = set.syntax().descendants().skip(1).find_map(ast::AttrSet::cast).unwrap();
+        nested.add_entry(inner);
+        assert_eq!(
+            set.to_string(),
+            "{\n  x = {\n    a = 1; # one\n    b = {\n      c = 3;\n    };\n  };\n}"
+        );
+
+        let set: ast::AttrSet = tree("{\n  x = {\n  };\n}");
+        let nested = set.syntax().descendants().skip(1).find_map(ast::AttrSet::cast).unwrap();
+        nested.add_entry(entry("{ a = 1; }"));
+        assert_eq!(set.to_string(), "{\n  x = {\n    a = 1;\n  };\n}");
+
+        let bindings = ast::AttrSet::parse_bindings("a = 1;\nb = 2;").tree();
+        let set = ast::AttrSet::cast(bindings.syntax().clone_for_update()).unwrap();
+        set.add_entry(entry("{ c = 3; }"));
+        assert_eq!(set.to_string(), "a = 1;\nb = 2;\nc = 3;");
+
+        let bindings = ast::AttrSet::parse_bindings("").tree();
+        let set = ast::AttrSet::cast(bindings.syntax().clone_for_update()).unwrap();
+        set.add_entry(entry("{ c = 3; }"));
+        assert_eq!(set.to_string(), "c = 3;");
+    }
+
+    #[test]
+    fn remove_entry() {
+        let set: ast::AttrSet = tree("{\n  a = 1; # one\n  # two\n  b = 2;\n}");
+        set.remove_entry(&set.entries().next().unwrap());
+        assert_eq!(set.to_string(), "{\n  # two\n  b = 2;\n}");
+        set.remove_entry(&set.entries().next().unwrap());
+        assert_eq!(set.to_string(), "{\n  # two\n}");
+
+        let set: ast::AttrSet = tree("{ a = 1; b = 2; }");
+        set.remove_entry(&set.entries().nth(1).unwrap());
+        assert_eq!(set.to_string(), "{ a = 1; }");
+    }
+
+    #[test]
+    fn let_in() {
+        let let_in: ast::LetIn = tree("let\n  a = 1;\nin a");
+        let_in.add_binding(entry("{ b = [\n  1\n]; }"));
+        assert_eq!(let_in.to_string(), "let\n  a = 1;\n  b = [\n    1\n  ];\nin a");
+
+        let let_in: ast::LetIn = tree("let in a");
+        let_in.add_binding(entry("{ b = 2; }"));
+        assert_eq!(let_in.to_string(), "let b = 2; in a");
+    }
+
+    #[test]
+    fn set_value() {
+        let set: ast::AttrSet = tree("{\n  a = 1;\n}");
+        let value = set.attrpath_values().next().unwrap();
+        value.set_value(expr("{\n  b = 2;\n}"));
+        assert_eq!(set.to_string(), "{\n  a = {\n    b = 2;\n  };\n}");
+    }
+
+    #[test]
+    fn list() {
+        let list: ast::List = tree("[ 1 2 ]");
+        list.push_item(expr("3"));
+        list.insert_item(0, expr("0"));
+        list.insert_item(2, expr("x"));
+        assert_eq!(list.to_string(), "[ 0 1 x 2 3 ]");
+        list.remove_item(0);
+        list.remove_item(3);
+        assert_eq!(list.to_string(), "[ 1 x 2 ]");
+
+        let list: ast::List = tree("[]");
+        list.push_item(expr("1"));
+        assert_eq!(list.to_string(), "[ 1 ]");
+
+        let list: ast::List = tree("[\n  1\n]");
+        list.push_item(expr("2"));
+        assert_eq!(list.to_string(), "[\n  1\n  2\n]");
+    }
+
+    #[test]
+    fn pattern() {
+        fn add(src: &str, name: &str) -> String {
+            let pattern: ast::Pattern = tree(src);
+            let lambda: ast::Pattern = tree(&format!("{{ {name} }}: x"));
+            pattern.add_entry(lambda.pat_entries().next().unwrap());
+            pattern.to_string()
+        }
+
+        assert_eq!(add("{ a, b }: x", "c"), "{ a, b, c }");
+        assert_eq!(add("{ a }: x", "b ? 1"), "{ a, b ? 1 }");
+        assert_eq!(add("{ a, ... }: x", "b"), "{ a, b, ... }");
+        assert_eq!(add("{ ... }: x", "a"), "{ a, ... }");
+        assert_eq!(add("{}: x", "a"), "{ a }");
+        assert_eq!(add("{\n  a,\n  b,\n}: x", "c"), "{\n  a,\n  b,\n  c,\n}");
+        assert_eq!(add("{ a\n, b\n}: x", "c"), "{ a\n, b\n, c\n}");
+    }
+}
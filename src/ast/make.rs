@@ -0,0 +1,533 @@
+//! Constructors for typed nodes.
+//!
+//! Each function writes out the Nix code for the node, adding parentheses
+//! where the precedence of an operand requires them, and parses it. The
+//! result is exactly the tree [`Root::parse`](crate::Root::parse) produces
+//! for that text, as a root of its own. The functions panic if the code
+//! doesn't parse, for example if `ident` is called with a keyword.
+
+use rowan::ast::AstNode;
+
+use crate::{
+    ast::{self, attr_util::quote, BinOpKind, InterpolPart, UnaryOpKind},
+    NixLanguage,
+    SyntaxKind::*,
+    SyntaxNode,
+};
+
+pub fn root(expr: ast::Expr) -> ast::Root {
+    ast::Root::cast(parse(&expr.to_string())).unwrap()
+}
+
+pub fn ident(name: &str) -> ast::Ident {
+    from_text(name)
+}
+
+/// An integer literal, or a negated one for negative values. `i64::MIN` has
+/// no literal, so it's made as `-9223372036854775807 - 1`.
+pub fn int(value: i64) -> ast::Expr {
+    if value == i64::MIN {
+        return from_text(&format!("-{} - 1", i64::MAX));
+    }
+    from_text(&value.to_string())
+}
+
+/// A float literal, or a negated one for negative values. Panics for NaN and
+/// infinite values.
+pub fn float(value: f64) -> ast::Expr {
+    assert!(value.is_finite(), "{value} can't be written as a float literal");
+    let mut text = format!("{value:?}");
+    // Nix reads `1e5` as an integer followed by an identifier
+    if !text.contains('.') {
+        if let Some(e) = text.find('e') {
+            text.insert_str(e, ".0");
+        }
+    }
+    from_text(&text)
+}
+
+/// A path, such as `./foo` or `<nixpkgs>`
+pub fn path(text: &str) -> ast::Path {
+    from_text(text)
+}
+
+/// A string literal with the content `s`, escaped as needed
+pub fn string(s: &str) -> ast::Str {
+    from_text(&quote(s))
+}
+
+/// A string literal made of text and interpolations
+pub fn string_parts(parts: impl IntoIterator<Item = InterpolPart<String>>) -> ast::Str {
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            InterpolPart::Literal(literal) => {
+                let quoted = quote(&literal);
+                text.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            InterpolPart::Interpolation(interpol) => text.push_str(&interpol.to_string()),
+        }
+    }
+    from_text(&format!("\"{text}\""))
+}
+
+pub fn interpol(expr: ast::Expr) -> ast::Interpol {
+    from_text(&format!("\"${{{expr}}}\""))
+}
+
+pub fn dynamic(expr: ast::Expr) -> ast::Dynamic {
+    from_text(&format!("{{ ${{{expr}}} = null; }}"))
+}
+
+/// An attribute called `name`, which is quoted if it isn't a valid identifier
+pub fn attr(name: &str) -> ast::Attr {
+    ast::Attr::from_name(name)
+}
+
+/// Panics if `attrs` is empty
+pub fn attrpath(attrs: impl IntoIterator<Item = ast::Attr>) -> ast::Attrpath {
+    from_text(&format!("{{ {} = null; }}", join(attrs, ".")))
+}
+
+pub fn attrpath_value(attrpath: ast::Attrpath, value: ast::Expr) -> ast::AttrpathValue {
+    from_text(&format!("{{ {attrpath} = {value}; }}"))
+}
+
+pub fn inherit(attrs: impl IntoIterator<Item = ast::Attr>) -> ast::Inherit {
+    from_text(&format!("{{ inherit {}; }}", join(attrs, " ")))
+}
+
+pub fn inherit_from(from: ast::Expr, attrs: impl IntoIterator<Item = ast::Attr>) -> ast::Inherit {
+    from_text(&format!("{{ inherit ({from}) {}; }}", join(attrs, " ")))
+}
+
+pub fn attr_set(entries: impl IntoIterator<Item = ast::Entry>, rec: bool) -> ast::AttrSet {
+    let rec = if rec { "rec " } else { "" };
+    from_text(&format!("{rec}{{{}}}", bindings(entries)))
+}
+
+pub fn let_in(entries: impl IntoIterator<Item = ast::Entry>, body: ast::Expr) -> ast::LetIn {
+    from_text(&format!("let{}in {body}", bindings(entries)))
+}
+
+pub fn legacy_let(entries: impl IntoIterator<Item = ast::Entry>) -> ast::LegacyLet {
+    from_text(&format!("let {{{}}}", bindings(entries)))
+}
+
+pub fn list(items: impl IntoIterator<Item = ast::Expr>) -> ast::List {
+    let items: Vec<_> = items.into_iter().map(|item| operand(item, SELECT)).collect();
+    if items.is_empty() {
+        from_text("[ ]")
+    } else {
+        from_text(&format!("[ {} ]", items.join(" ")))
+    }
+}
+
+pub fn paren(expr: ast::Expr) -> ast::Paren {
+    from_text(&format!("({expr})"))
+}
+
+pub fn ident_param(name: &str) -> ast::IdentParam {
+    from_text(&format!("{name}: null"))
+}
+
+pub fn pat_entry(name: &str, default: Option<ast::Expr>) -> ast::PatEntry {
+    match default {
+        Some(default) => from_text(&format!("{{ {name} ? {default} }}: null")),
+        None => from_text(&format!("{{ {name} }}: null")),
+    }
+}
+
+pub fn pat_bind(name: &str) -> ast::PatBind {
+    from_text(&format!("{{ }}@{name}: null"))
+}
+
+/// A pattern like `{ a, b ? 1, ... }@args`
+pub fn pattern(
+    entries: impl IntoIterator<Item = ast::PatEntry>,
+    ellipsis: bool,
+    bind: Option<&str>,
+) -> ast::Pattern {
+    let mut items: Vec<String> = entries.into_iter().map(|entry| entry.to_string()).collect();
+    if ellipsis {
+        items.push("...".to_string());
+    }
+    let items = if items.is_empty() { " ".to_string() } else { format!(" {} ", items.join(", ")) };
+    let bind = bind.map(|name| format!("@{name}")).unwrap_or_default();
+    from_text(&format!("{{{items}}}{bind}: null"))
+}
+
+pub fn lambda(param: ast::Param, body: ast::Expr) -> ast::Lambda {
+    from_text(&format!("{param}: {body}"))
+}
+
+pub fn apply(lambda: ast::Expr, argument: ast::Expr) -> ast::Apply {
+    from_text(&format!("{} {}", operand(lambda, APPLY), operand(argument, SELECT)))
+}
+
+pub fn select(expr: ast::Expr, attrpath: ast::Attrpath, default: Option<ast::Expr>) -> ast::Select {
+    let expr = selected(expr);
+    match default {
+        Some(default) => from_text(&format!("{expr}.{attrpath} or {}", operand(default, SELECT))),
+        None => from_text(&format!("{expr}.{attrpath}")),
+    }
+}
+
+pub fn has_attr(expr: ast::Expr, attrpath: ast::Attrpath) -> ast::HasAttr {
+    // `?` is non-associative, so `a ? b ? c` isn't valid Nix
+    from_text(&format!("{} ? {attrpath}", operand(expr, HAS_ATTR + 1)))
+}
+
+pub fn unary_op(operator: UnaryOpKind, expr: ast::Expr) -> ast::UnaryOp {
+    let (symbol, min) = match operator {
+        UnaryOpKind::Invert => ("!", INVERT),
+        UnaryOpKind::Negate => ("-", NEGATE),
+    };
+    from_text(&format!("{symbol}{}", operand(expr, min)))
+}
+
+pub fn bin_op(lhs: ast::Expr, operator: BinOpKind, rhs: ast::Expr) -> ast::BinOp {
+    from_text(&format!(
+        "{} {} {}",
+        bin_op_operand(lhs, operator, true),
+        bin_op_symbol(operator),
+        bin_op_operand(rhs, operator, false)
+    ))
+}
+
+pub fn if_else(condition: ast::Expr, body: ast::Expr, else_body: ast::Expr) -> ast::IfElse {
+    from_text(&format!("if {condition} then {body} else {else_body}"))
+}
+
+pub fn with(namespace: ast::Expr, body: ast::Expr) -> ast::With {
+    from_text(&format!("with {namespace}; {body}"))
+}
+
+pub fn assert(condition: ast::Expr, body: ast::Expr) -> ast::Assert {
+    from_text(&format!("assert {condition}; {body}"))
+}
+
+// Precedence levels, from loosest to tightest. Expressions that extend as
+// far to the right as possible, like lambdas and `let`, are always
+// parenthesized as operands.
+const PIPE: u8 = 1;
+const IMPLICATION: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const EQUAL: u8 = 5;
+const COMPARE: u8 = 6;
+const UPDATE: u8 = 7;
+const INVERT: u8 = 8;
+const ADD: u8 = 9;
+const MUL: u8 = 10;
+const CONCAT: u8 = 11;
+const HAS_ATTR: u8 = 12;
+const NEGATE: u8 = 13;
+const APPLY: u8 = 14;
+const SELECT: u8 = 15;
+const ATOM: u8 = 16;
+
+enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+fn bin_op_precedence(operator: BinOpKind) -> u8 {
+    match operator {
+        BinOpKind::PipeRight | BinOpKind::PipeLeft => PIPE,
+        BinOpKind::Implication => IMPLICATION,
+        BinOpKind::Or => OR,
+        BinOpKind::And => AND,
+        BinOpKind::Equal | BinOpKind::NotEqual => EQUAL,
+        BinOpKind::Less | BinOpKind::LessOrEq | BinOpKind::More | BinOpKind::MoreOrEq => COMPARE,
+        BinOpKind::Update => UPDATE,
+        BinOpKind::Add | BinOpKind::Sub => ADD,
+        BinOpKind::Mul | BinOpKind::Div => MUL,
+        BinOpKind::Concat => CONCAT,
+    }
+}
+
+/// The minimum precedence of the left and right operand of `operator`
+fn bin_op_operands(operator: BinOpKind) -> (u8, u8) {
+    let precedence = bin_op_precedence(operator);
+    match associativity(operator) {
+        Assoc::Left => (precedence, precedence + 1),
+        Assoc::Right => (precedence + 1, precedence),
+        Assoc::None => (precedence + 1, precedence + 1),
+    }
+}
+
+fn associativity(operator: BinOpKind) -> Assoc {
+    match operator {
+        BinOpKind::PipeRight => Assoc::Left,
+        BinOpKind::PipeLeft => Assoc::Right,
+        _ => match bin_op_precedence(operator) {
+            IMPLICATION | UPDATE | CONCAT => Assoc::Right,
+            EQUAL | COMPARE => Assoc::None,
+            _ => Assoc::Left,
+        },
+    }
+}
+
+/// The text of the left (`first`) or right operand of `operator`. `|>` and
+/// `<|` have the same precedence, but can't be mixed without parentheses.
+fn bin_op_operand(expr: ast::Expr, operator: BinOpKind, first: bool) -> String {
+    let is_pipe = |operator| matches!(operator, BinOpKind::PipeRight | BinOpKind::PipeLeft);
+    let mixed = match &expr {
+        ast::Expr::BinOp(op) => op
+            .operator()
+            .is_some_and(|inner| inner != operator && is_pipe(inner) && is_pipe(operator)),
+        _ => false,
+    };
+    let (lhs_min, rhs_min) = bin_op_operands(operator);
+    let min = if mixed {
+        PIPE + 1
+    } else if first {
+        lhs_min
+    } else {
+        rhs_min
+    };
+    operand(expr, min)
+}
+
+fn bin_op_symbol(operator: BinOpKind) -> &'static str {
+    match operator {
+        BinOpKind::Concat => "++",
+        BinOpKind::Update => "//",
+        BinOpKind::Add => "+",
+        BinOpKind::Sub => "-",
+        BinOpKind::Mul => "*",
+        BinOpKind::Div => "/",
+        BinOpKind::And => "&&",
+        BinOpKind::Equal => "==",
+        BinOpKind::Implication => "->",
+        BinOpKind::Less => "<",
+        BinOpKind::LessOrEq => "<=",
+        BinOpKind::More => ">",
+        BinOpKind::MoreOrEq => ">=",
+        BinOpKind::NotEqual => "!=",
+        BinOpKind::Or => "||",
+        BinOpKind::PipeLeft => "<|",
+        BinOpKind::PipeRight => "|>",
+    }
+}
+
+fn precedence(expr: &ast::Expr) -> u8 {
+    match expr {
+        ast::Expr::Root(root) => root.expr().map_or(0, |expr| precedence(&expr)),
+        ast::Expr::BinOp(op) => op.operator().map_or(0, bin_op_precedence),
+        ast::Expr::UnaryOp(op) => match op.operator() {
+            Some(UnaryOpKind::Invert) => INVERT,
+            Some(UnaryOpKind::Negate) => NEGATE,
+            None => 0,
+        },
+        ast::Expr::HasAttr(_) => HAS_ATTR,
+        ast::Expr::Apply(_) => APPLY,
+        ast::Expr::Select(_) => SELECT,
+        ast::Expr::Ident(_)
+        | ast::Expr::Literal(_)
+        | ast::Expr::Str(_)
+        | ast::Expr::Path(_)
+        | ast::Expr::List(_)
+        | ast::Expr::AttrSet(_)
+        | ast::Expr::Paren(_)
+        | ast::Expr::LegacyLet(_) => ATOM,
+        ast::Expr::Lambda(_)
+        | ast::Expr::Assert(_)
+        | ast::Expr::IfElse(_)
+        | ast::Expr::LetIn(_)
+        | ast::Expr::With(_)
+        | ast::Expr::Error(_) => 0,
+    }
+}
+
+/// The text of `expr`, in parentheses if it binds less tightly than `min`
+fn operand(expr: ast::Expr, min: u8) -> String {
+    if precedence(&expr) < min {
+        format!("({expr})")
+    } else {
+        expr.to_string()
+    }
+}
+
+/// The text of `expr` as the expression that an attribute is selected from
+fn selected(expr: ast::Expr) -> String {
+    match expr {
+        // A path would take the `.` as part of its name
+        ast::Expr::Path(_) | ast::Expr::Literal(_) => format!("({expr})"),
+        expr => operand(expr, ATOM),
+    }
+}
+
+/// The text of `expr` to replace `node` with, in parentheses if needed at the
+/// position of `node`
+pub(super) fn operand_at(expr: ast::Expr, node: &SyntaxNode) -> String {
+    let Some(parent) = node.parent() else { return expr.to_string() };
+    let first = parent.children().next().as_ref() == Some(node);
+    let min = match parent.kind() {
+        NODE_BIN_OP => match ast::BinOp::cast(parent).and_then(|op| op.operator()) {
+            Some(operator) => return bin_op_operand(expr, operator, first),
+            None => 0,
+        },
+        NODE_UNARY_OP => match ast::UnaryOp::cast(parent).and_then(|op| op.operator()) {
+            Some(UnaryOpKind::Invert) => INVERT,
+            Some(UnaryOpKind::Negate) => NEGATE,
+            None => 0,
+        },
+        NODE_APPLY if first => APPLY,
+        NODE_APPLY | NODE_LIST => SELECT,
+        NODE_SELECT if first => return selected(expr),
+        NODE_SELECT => SELECT,
+        NODE_HAS_ATTR => HAS_ATTR + 1,
+        _ => 0,
+    };
+    operand(expr, min)
+}
+
+fn bindings(entries: impl IntoIterator<Item = ast::Entry>) -> String {
+    entries.into_iter().map(|entry| format!(" {entry}")).chain([" ".to_string()]).collect()
+}
+
+fn join(items: impl IntoIterator<Item = impl ToString>, separator: &str) -> String {
+    items.into_iter().map(|item| item.to_string()).collect::<Vec<_>>().join(separator)
+}
+
+/// Parse `text` and return the first node of type `N`, other than the root,
+/// as a new root
+fn from_text<N: AstNode<Language = NixLanguage>>(text: &str) -> N {
+    parse(text)
+        .descendants()
+        .find(|node| node.kind() != NODE_ROOT && N::can_cast(node.kind()))
+        .and_then(|node| N::cast(node.clone_subtree()))
+        .unwrap_or_else(|| panic!("{text:?} doesn't contain the node to make"))
+}
+
+fn parse(text: &str) -> SyntaxNode {
+    let parse = crate::Root::parse(text);
+    if let Some(error) = parse.errors().first() {
+        panic!("failed to make a node from {text:?}: {error}");
+    }
+    parse.syntax()
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::ast::AstNode;
+
+    use super::*;
+
+    /// Check that `node` has the same tree as `text` when it's parsed
+    #[track_caller]
+    fn check(node: impl AstNode<Language = NixLanguage>, text: &str) {
+        let parsed = crate::Root::parse(text).ok().unwrap();
+        let parsed = parsed.expr().unwrap();
+        assert_eq!(node.syntax().to_string(), text);
+        assert_eq!(format!("{:#?}", node.syntax()), format!("{:#?}", parsed.syntax()));
+    }
+
+    fn id(name: &str) -> ast::Expr {
+        ident(name).into()
+    }
+
+    #[test]
+    fn literals() {
+        check(int(42), "42");
+        check(int(-1), "-1");
+        check(int(i64::MIN), "-9223372036854775807 - 1");
+        check(list([int(i64::MIN)]), "[ (-9223372036854775807 - 1) ]");
+        check(float(1.5), "1.5");
+        check(float(1e300), "1.0e300");
+        check(path("./a/b"), "./a/b");
+        check(string("a \"b\"\n${c} $d \\"), r#""a \"b\"\n\${c} $d \\""#);
+        check(
+            string_parts([
+                InterpolPart::Literal("a${".to_string()),
+                InterpolPart::Interpolation(interpol(id("b"))),
+            ]),
+            r#""a\${${b}""#,
+        );
+    }
+
+    #[test]
+    fn sets() {
+        let entries: [ast::Entry; 3] = [
+            attrpath_value(attrpath([attr("a"), attr("b c")]), int(1)).into(),
+            inherit([attr("x"), attr("y")]).into(),
+            inherit_from(id("pkgs"), [attr("hello")]).into(),
+        ];
+        check(
+            attr_set(entries.clone(), true),
+            r#"rec { a."b c" = 1; inherit x y; inherit (pkgs) hello; }"#,
+        );
+        check(attr_set([], false), "{ }");
+        let body = attrpath_value(attrpath([attr("body")]), int(1));
+        check(legacy_let([body.into()]), "let { body = 1; }");
+        check(
+            let_in(entries, id("x")),
+            r#"let a."b c" = 1; inherit x y; inherit (pkgs) hello; in x"#,
+        );
+        let dynamic = attrpath([ast::Attr::Dynamic(dynamic(id("n")))]);
+        check(attr_set([attrpath_value(dynamic, id("v")).into()], false), "{ ${n} = v; }");
+    }
+
+    #[test]
+    fn functions() {
+        let entries = [pat_entry("a", None), pat_entry("b", Some(int(1)))];
+        check(
+            lambda(pattern(entries, true, Some("args")).into(), id("a")),
+            "{ a, b ? 1, ... }@args: a",
+        );
+        check(lambda(pattern([], false, None).into(), id("a")), "{ }: a");
+        let inner = lambda(ident_param("x").into(), id("x"));
+        check(
+            apply(apply(id("f"), inner.into()).into(), list([int(1), id("a")]).into()),
+            "f (x: x) [ 1 a ]",
+        );
+        check(apply(id("f"), apply(id("g"), id("x")).into()), "f (g x)");
+    }
+
+    #[test]
+    fn operators() {
+        let sum: ast::Expr = bin_op(int(1), BinOpKind::Add, int(2)).into();
+        check(bin_op(sum.clone(), BinOpKind::Mul, int(3)), "(1 + 2) * 3");
+        check(bin_op(int(3), BinOpKind::Sub, sum.clone()), "3 - (1 + 2)");
+        check(bin_op(sum.clone(), BinOpKind::Sub, int(3)), "1 + 2 - 3");
+        let update: ast::Expr = bin_op(id("a"), BinOpKind::Update, id("b")).into();
+        check(bin_op(id("x"), BinOpKind::Update, update.clone()), "x // a // b");
+        check(bin_op(update, BinOpKind::Update, id("x")), "(a // b) // x");
+        let equal: ast::Expr = bin_op(id("a"), BinOpKind::Equal, id("b")).into();
+        check(bin_op(equal, BinOpKind::Equal, id("c")), "(a == b) == c");
+        let forward: ast::Expr = bin_op(id("x"), BinOpKind::PipeRight, id("f")).into();
+        let backward: ast::Expr = bin_op(id("f"), BinOpKind::PipeLeft, id("x")).into();
+        check(bin_op(forward.clone(), BinOpKind::PipeRight, id("g")), "x |> f |> g");
+        check(bin_op(id("g"), BinOpKind::PipeLeft, backward.clone()), "g <| f <| x");
+        check(bin_op(backward.clone(), BinOpKind::PipeRight, id("g")), "(f <| x) |> g");
+        check(bin_op(id("g"), BinOpKind::PipeLeft, forward.clone()), "g <| (x |> f)");
+        check(bin_op(forward, BinOpKind::PipeLeft, backward), "(x |> f) <| f <| x");
+        check(unary_op(UnaryOpKind::Negate, apply(id("f"), id("x")).into()), "-f x");
+        check(unary_op(UnaryOpKind::Invert, sum), "!1 + 2");
+        let path = attrpath([attr("b")]);
+        let selected: ast::Expr = select(id("a"), path.clone(), None).into();
+        check(select(selected.clone(), path.clone(), Some(id("c"))), "(a.b).b or c");
+        check(select(path_expr(), path.clone(), None), "(./x).b");
+        check(has_attr(selected, path.clone()), "a.b ? b");
+        let nested: ast::Expr = has_attr(id("a"), path.clone()).into();
+        check(has_attr(nested, path), "(a ? b) ? b");
+        check(
+            if_else(id("c"), with(id("a"), id("b")).into(), assert(id("c"), id("d")).into()),
+            "if c then with a; b else assert c; d",
+        );
+        check(paren(let_in([], id("x")).into()), "(let in x)");
+    }
+
+    fn path_expr() -> ast::Expr {
+        path("./x").into()
+    }
+
+    #[test]
+    #[should_panic]
+    fn keyword_ident() {
+        ident("if");
+    }
+}
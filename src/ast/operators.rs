@@ -19,6 +19,9 @@ pub enum BinOpKind {
     MoreOrEq,
     NotEqual,
     Or,
+
+    PipeLeft,
+    PipeRight,
 }
 
 impl BinOpKind {
@@ -43,6 +46,9 @@ impl BinOpKind {
             TOKEN_NOT_EQUAL => Some(BinOpKind::NotEqual),
             TOKEN_OR_OR => Some(BinOpKind::Or),
 
+            TOKEN_PIPE_LEFT => Some(BinOpKind::PipeLeft),
+            TOKEN_PIPE_RIGHT => Some(BinOpKind::PipeRight),
+
             _ => None,
         }
     }
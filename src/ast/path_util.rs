@@ -1,3 +1,5 @@
+use std::path::Path as FsPath;
+
 use crate::{ast::AstToken, kinds::SyntaxKind::*};
 use rowan::{ast::AstNode as OtherAstNode, NodeOrToken};
 
@@ -5,6 +7,19 @@ use crate::ast;
 
 use super::{InterpolPart, PathContent};
 
+/// How a path literal starts, which decides what it is relative to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PathKind {
+    /// `./foo`, `../foo` or `foo/bar`, relative to the file it's in
+    Relative,
+    /// `/foo`
+    Absolute,
+    /// `~/foo`, relative to the home directory
+    Home,
+    /// `<nixpkgs/lib>`, looked up in the search path
+    Search,
+}
+
 impl ast::nodes::Path {
     pub fn parts(&self) -> impl Iterator<Item = InterpolPart<PathContent>> {
         self.syntax().children_with_tokens().map(|child| match child {
@@ -17,17 +32,166 @@ impl ast::nodes::Path {
             }
         })
     }
+
+    /// The text of the path up to the first interpolation
+    fn prefix(&self) -> Option<String> {
+        match self.parts().next()? {
+            InterpolPart::Literal(content) => Some(content.syntax().text().to_string()),
+            InterpolPart::Interpolation(_) => None,
+        }
+    }
+
+    pub fn kind(&self) -> Option<PathKind> {
+        let prefix = self.prefix()?;
+        Some(match prefix.chars().next()? {
+            '<' => PathKind::Search,
+            '~' => PathKind::Home,
+            '/' => PathKind::Absolute,
+            _ => PathKind::Relative,
+        })
+    }
+
+    /// The text between `<` and `>` of a search path, such as `nixpkgs/lib`
+    fn search_path(&self) -> Option<String> {
+        let prefix = self.prefix()?;
+        Some(prefix.strip_prefix('<')?.strip_suffix('>')?.to_string())
+    }
+
+    /// The name that is looked up in the search path, such as `nixpkgs` for
+    /// `<nixpkgs/lib>`
+    pub fn search_path_name(&self) -> Option<String> {
+        let path = self.search_path()?;
+        Some(path.split('/').next()?.to_string())
+    }
+
+    /// The path below the search path entry, such as `lib` for
+    /// `<nixpkgs/lib>`. None for search paths like `<nixpkgs>`.
+    pub fn search_path_subpath(&self) -> Option<String> {
+        let path = self.search_path()?;
+        Some(path.split_once('/')?.1.to_string())
+    }
+
+    /// Resolve a relative path against `base_dir`, the directory of the file
+    /// it is in, the same way Nix does. `.` and `..` are removed from the
+    /// text before the first interpolation. Home paths are returned as they
+    /// are and search paths return None, since they depend on the environment.
+    pub fn resolve(&self, base_dir: &FsPath) -> Option<Vec<InterpolPart<String>>> {
+        let kind = self.kind()?;
+        let mut parts: Vec<_> = self
+            .parts()
+            .map(|part| match part {
+                InterpolPart::Literal(content) => {
+                    InterpolPart::Literal(content.syntax().text().to_string())
+                }
+                InterpolPart::Interpolation(interpol) => InterpolPart::Interpolation(interpol),
+            })
+            .collect();
+        if let Some(InterpolPart::Literal(prefix)) = parts.first_mut() {
+            *prefix = resolve_prefix(kind, prefix, base_dir)?;
+        }
+        Some(parts)
+    }
+}
+
+/// Resolve the text of a path before its first interpolation, see
+/// [`ast::Path::resolve`]
+pub(crate) fn resolve_prefix(kind: PathKind, prefix: &str, base_dir: &FsPath) -> Option<String> {
+    let full = match kind {
+        PathKind::Search => return None,
+        PathKind::Home => return Some(prefix.to_string()),
+        PathKind::Absolute => prefix.to_string(),
+        PathKind::Relative => format!("{}/{}", base_dir.to_string_lossy(), prefix),
+    };
+    let mut resolved = canonicalize(&full);
+    if prefix.ends_with('/') && !resolved.ends_with('/') {
+        resolved.push('/');
+    }
+    Some(resolved)
+}
+
+/// Remove `.`, `..` and repeated slashes from a path without looking at the file
+/// system, like Nix's `canonPath`
+fn canonicalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." if components.last().is_some_and(|last| *last != "..") => {
+                components.pop();
+            }
+            // Above the root of an absolute path
+            ".." if path.starts_with('/') => (),
+            _ => components.push(component),
+        }
+    }
+    let mut resolved = components.join("/");
+    if path.starts_with('/') {
+        resolved.insert(0, '/');
+    }
+    resolved
 }
 
 #[cfg(test)]
 mod tests {
     use rowan::ast::AstNode;
 
+    use std::path::Path;
+
     use crate::{
-        ast::{self, AstToken, InterpolPart, PathContent},
+        ast::{self, AstToken, InterpolPart, PathContent, PathKind},
         Root,
     };
 
+    fn path(inp: &str) -> ast::Path {
+        match Root::parse(inp).ok().unwrap().expr().unwrap() {
+            ast::Expr::Path(p) => p,
+            _ => unreachable!(),
+        }
+    }
+
+    fn literals(parts: Vec<InterpolPart<String>>) -> Vec<Option<String>> {
+        parts
+            .into_iter()
+            .map(|part| match part {
+                InterpolPart::Literal(s) => Some(s),
+                InterpolPart::Interpolation(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        assert_eq!(path("./a").kind(), Some(PathKind::Relative));
+        assert_eq!(path("a/b").kind(), Some(PathKind::Relative));
+        assert_eq!(path("/a").kind(), Some(PathKind::Absolute));
+        assert_eq!(path("~/a").kind(), Some(PathKind::Home));
+        assert_eq!(path("<nixpkgs/lib>").kind(), Some(PathKind::Search));
+
+        let p = path("<nixpkgs/lib/default.nix>");
+        assert_eq!(p.search_path_name().as_deref(), Some("nixpkgs"));
+        assert_eq!(p.search_path_subpath().as_deref(), Some("lib/default.nix"));
+        let p = path("<nixpkgs>");
+        assert_eq!(p.search_path_name().as_deref(), Some("nixpkgs"));
+        assert_eq!(p.search_path_subpath(), None);
+        assert_eq!(path("./a").search_path_name(), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let base = Path::new("/etc/nixos");
+        let resolve = |inp: &str| path(inp).resolve(base).map(literals);
+        assert_eq!(resolve("./a/b.nix"), Some(vec![Some("/etc/nixos/a/b.nix".into())]));
+        assert_eq!(resolve("../../../a"), Some(vec![Some("/a".into())]));
+        assert_eq!(resolve("a/./b/../c"), Some(vec![Some("/etc/nixos/a/c".into())]));
+        assert_eq!(
+            resolve("../a/${b}/../c"),
+            Some(vec![Some("/etc/a/".into()), None, Some("/../c".into())])
+        );
+        assert_eq!(resolve("/a/../b"), Some(vec![Some("/b".into())]));
+        assert_eq!(resolve("~/a"), Some(vec![Some("~/a".into())]));
+        assert_eq!(resolve("<nixpkgs>"), None);
+    }
+
     #[test]
     fn parts() {
         fn assert_eq_ast_ctn(it: &mut dyn Iterator<Item = InterpolPart<PathContent>>, x: &str) {
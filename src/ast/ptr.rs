@@ -0,0 +1,124 @@
+use std::{fmt, hash, iter::successors, marker::PhantomData};
+
+use rowan::{ast::AstNode, TextRange};
+
+use crate::{ast, NixLanguage, SyntaxKind};
+
+/// A pointer to a node that isn't tied to one tree: it remembers the node's
+/// kind and range, and finds the node again in another tree of the same text,
+/// for example after parsing it again.
+pub struct AstPtr<N> {
+    kind: SyntaxKind,
+    range: TextRange,
+    _ty: PhantomData<fn() -> N>,
+}
+
+impl<N: AstNode<Language = NixLanguage>> AstPtr<N> {
+    pub fn new(node: &N) -> Self {
+        let node = node.syntax();
+        AstPtr { kind: node.kind(), range: node.text_range(), _ty: PhantomData }
+    }
+
+    /// Find the node in `root`. Panics if it isn't there, use `try_to_node`
+    /// if the text might have changed.
+    pub fn to_node(&self, root: &ast::Root) -> N {
+        self.try_to_node(root)
+            .unwrap_or_else(|| panic!("can't find {:?} at {:?}", self.kind, self.range))
+    }
+
+    /// Find the node in `root`, if there still is a node of the same kind at
+    /// the same position
+    pub fn try_to_node(&self, root: &ast::Root) -> Option<N> {
+        let root = root.syntax();
+        if !root.text_range().contains_range(self.range) {
+            return None;
+        }
+        successors(Some(root.clone()), |node| node.child_or_token_at_range(self.range)?.into_node())
+            .find(|node| node.text_range() == self.range && node.kind() == self.kind)
+            .and_then(N::cast)
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text_range(&self) -> TextRange {
+        self.range
+    }
+
+    /// Turn this into a pointer to another type of node, if the node can be one
+    pub fn cast<U: AstNode<Language = NixLanguage>>(self) -> Option<AstPtr<U>> {
+        U::can_cast(self.kind).then_some(AstPtr {
+            kind: self.kind,
+            range: self.range,
+            _ty: PhantomData,
+        })
+    }
+}
+
+// Implemented by hand, since deriving would require the same traits from `N`
+
+impl<N> Clone for AstPtr<N> {
+    fn clone(&self) -> Self {
+        AstPtr { kind: self.kind, range: self.range, _ty: PhantomData }
+    }
+}
+
+impl<N> PartialEq for AstPtr<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.range == other.range
+    }
+}
+
+impl<N> Eq for AstPtr<N> {}
+
+impl<N> hash::Hash for AstPtr<N> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.range.hash(state);
+    }
+}
+
+impl<N> fmt::Debug for AstPtr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AstPtr").field("kind", &self.kind).field("range", &self.range).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::{ast::AstNode, TextRange};
+
+    use super::AstPtr;
+    use crate::{ast, Root};
+
+    #[test]
+    fn survives_reparse() {
+        let code = "{ f = x: x; g = { a, b }: a; }";
+        let root = Root::parse(code).tree();
+        let lambdas: Vec<_> = root.syntax().descendants().filter_map(ast::Lambda::cast).collect();
+        let ptrs: Vec<_> = lambdas.iter().map(AstPtr::new).collect();
+
+        let again = Root::parse(code).tree();
+        for (ptr, lambda) in ptrs.iter().zip(&lambdas) {
+            let found = ptr.to_node(&again);
+            assert_eq!(found.syntax().to_string(), lambda.syntax().to_string());
+            assert_ne!(found.syntax(), lambda.syntax());
+        }
+
+        // Only the nodes before the edit are still found
+        let parse = Root::parse(code).reparse(TextRange::new(17.into(), 18.into()), "abc");
+        let edited = parse.tree();
+        assert!(ptrs[0].try_to_node(&edited).is_some());
+        assert!(ptrs[1].try_to_node(&edited).is_none());
+        assert!(ptrs[0].try_to_node(&Root::parse("").tree()).is_none());
+
+        let value = root.syntax().descendants().find_map(ast::AttrpathValue::cast).unwrap();
+        let ptr = AstPtr::new(&value);
+        assert!(ptr.clone().cast::<ast::Lambda>().is_none());
+        assert_eq!(ptr.to_node(&again).attrpath().unwrap().to_string(), "f");
+
+        let expr: AstPtr<ast::Expr> = ptrs[0].clone().cast().unwrap();
+        assert!(matches!(expr.to_node(&again), ast::Expr::Lambda(_)));
+    }
+}
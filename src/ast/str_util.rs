@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use crate::kinds::SyntaxKind::*;
 use rowan::{ast::AstNode as OtherAstNode, NodeOrToken};
 
@@ -23,7 +25,7 @@ impl ast::Str {
     }
 
     pub fn normalized_parts(&self) -> Vec<InterpolPart<String>> {
-        let multiline = children_tokens_u(self).next().map_or(false, |t| t.text() == "''");
+        let multiline = children_tokens_u(self).next().is_some_and(|t| t.text() == "''");
         let mut is_first_literal = true;
         let mut at_start_of_line = true;
         let mut min_indent = 1000000;
@@ -44,7 +46,8 @@ impl ast::Str {
                         n += 1;
                     }
                     InterpolPart::Literal(literal) => {
-                        let mut token_text = literal.syntax().text();
+                        let text = normalize_newlines(literal.syntax().text());
+                        let mut token_text = &*text;
 
                         if n == 0 {
                             first_is_literal = true;
@@ -96,7 +99,8 @@ impl ast::Str {
                     i += 1;
                 }
                 InterpolPart::Literal(literal) => {
-                    let mut token_text = literal.syntax().text();
+                    let text = normalize_newlines(literal.syntax().text());
+                    let mut token_text = &*text;
 
                     if multiline {
                         if is_first_literal && first_is_literal {
@@ -158,6 +162,15 @@ impl ast::Str {
     }
 }
 
+/// Like Nix, turn `\r\n` line endings in strings into `\n`
+fn normalize_newlines(text: &str) -> Cow<'_, str> {
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
 /// Interpret escape sequences in the nix string and return the converted value
 pub fn unescape(input: &str, multiline: bool) -> String {
     let mut output = String::new();
@@ -238,6 +251,20 @@ mod tests {
         }
     }
     #[test]
+    fn parts_crlf() {
+        let inp = "''\r\n  hello\r\n\r\n  world\r\n''";
+        let expr = Root::parse(inp).ok().unwrap().expr().unwrap();
+        match expr {
+            ast::Expr::Str(str) => {
+                assert_eq!(
+                    str.normalized_parts(),
+                    vec![InterpolPart::Literal("hello\n\nworld\n".to_string())]
+                )
+            }
+            _ => unreachable!(),
+        }
+    }
+    #[test]
     fn parts_trailing_ws_single_line() {
         let inp = "''hello ''";
         let expr = Root::parse(inp).ok().unwrap().expr().unwrap();
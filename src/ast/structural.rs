@@ -0,0 +1,189 @@
+//! Comparing and hashing expressions by their structure instead of their
+//! text or their position in a tree.
+
+use rowan::{ast::AstNode, NodeOrToken};
+
+use crate::{
+    ast::{self, InterpolPart},
+    SyntaxKind::{self, *},
+    SyntaxNode,
+};
+
+impl ast::Expr {
+    /// Whether `self` and `other` are the same code, apart from whitespace,
+    /// comments and parentheses. Strings and attribute names are compared by
+    /// their value, so `"a"` is equal to `''a''` and `{ "b" = 1; }` to
+    /// `{ b = 1; }`.
+    pub fn structurally_eq(&self, other: &ast::Expr) -> bool {
+        events(self.syntax()) == events(other.syntax())
+    }
+
+    /// A 128-bit FNV-1a hash of the structure of the expression, which is the
+    /// same for expressions that are [`structurally_eq`](Self::structurally_eq).
+    /// It doesn't change between runs or platforms, but may change between
+    /// versions of rnix.
+    pub fn fingerprint(&self) -> u128 {
+        let mut hasher = Fnv1a::default();
+        for event in events(self.syntax()) {
+            hasher.event(&event);
+        }
+        hasher.0
+    }
+}
+
+/// A step of walking the structure of an expression
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    Enter(SyntaxKind),
+    Exit,
+    Token(SyntaxKind, String),
+    /// The value of a string or of an attribute name
+    Literal(String),
+}
+
+fn events(node: &SyntaxNode) -> Vec<Event> {
+    let mut events = Vec::new();
+    walk(node, &mut events);
+    events
+}
+
+fn walk(node: &SyntaxNode, events: &mut Vec<Event>) {
+    // The tree already groups the expression inside
+    if let Some(expr) = ast::Paren::cast(node.clone()).and_then(|paren| paren.expr()) {
+        return walk(expr.syntax(), events);
+    }
+    let in_attrpath = matches!(node.parent().map(|p| p.kind()), Some(NODE_ATTRPATH | NODE_INHERIT));
+    if let Some(name) =
+        ast::Attr::cast(node.clone()).filter(|_| in_attrpath).and_then(|attr| attr.static_name())
+    {
+        events.push(Event::Literal(name));
+        return;
+    }
+
+    events.push(Event::Enter(node.kind()));
+    if let Some(s) = ast::Str::cast(node.clone()) {
+        // Escapes and indentation may split up literals differently
+        let mut literal = String::new();
+        for part in s.normalized_parts() {
+            match part {
+                InterpolPart::Literal(text) => literal.push_str(&text),
+                InterpolPart::Interpolation(interpol) => {
+                    if !literal.is_empty() {
+                        events.push(Event::Literal(std::mem::take(&mut literal)));
+                    }
+                    walk(interpol.syntax(), events);
+                }
+            }
+        }
+        if !literal.is_empty() {
+            events.push(Event::Literal(literal));
+        }
+    } else {
+        for child in node.children_with_tokens() {
+            match child {
+                NodeOrToken::Node(node) => walk(&node, events),
+                NodeOrToken::Token(token)
+                    if !matches!(token.kind(), TOKEN_WHITESPACE | TOKEN_COMMENT) =>
+                {
+                    events.push(Event::Token(token.kind(), token.text().to_string()))
+                }
+                NodeOrToken::Token(_) => (),
+            }
+        }
+    }
+    events.push(Event::Exit);
+}
+
+struct Fnv1a(u128);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0x6c62272e07bb014262b821756295c58d)
+    }
+}
+
+impl Fnv1a {
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u128::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+
+    fn event(&mut self, event: &Event) {
+        match event {
+            Event::Enter(kind) => {
+                self.write(&[0]);
+                self.write(&(*kind as u16).to_le_bytes());
+            }
+            Event::Exit => self.write(&[1]),
+            Event::Token(kind, text) => {
+                self.write(&[2]);
+                self.write(&(*kind as u16).to_le_bytes());
+                self.write_str(text);
+            }
+            Event::Literal(text) => {
+                self.write(&[3]);
+                self.write_str(text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fnv1a;
+    use crate::ast;
+
+    fn expr(code: &str) -> ast::Expr {
+        ast::Expr::parse(code).ok().unwrap()
+    }
+
+    fn assert_same(a: &str, b: &str) {
+        let (a, b) = (expr(a), expr(b));
+        assert!(a.structurally_eq(&b), "{} should equal {}", a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+    }
+
+    fn assert_different(a: &str, b: &str) {
+        let (a, b) = (expr(a), expr(b));
+        assert!(!a.structurally_eq(&b), "{} shouldn't equal {}", a, b);
+        assert_ne!(a.fingerprint(), b.fingerprint());
+    }
+
+    #[test]
+    fn structurally_eq() {
+        assert_same("f  a # comment\n b", "f a b");
+        assert_same("((a + b)) * c", "(a + b) * c");
+        assert_same("(x: x)", "x: x");
+        assert_same("\"a\\nb${c}\"", "''\n  a\n  b${c}''");
+        assert_same("\"\\${a}\"", "''\n  ''${a}''");
+        assert_same("{ \"a\".${\"b\"} = 1; inherit \"c\"; }", "{ a.b = 1; inherit c; }");
+        assert_same("x.\"a\" or 1", "x.a or 1");
+
+        assert_different("a + b", "a - b");
+        assert_different("f a b", "f (a b)");
+        assert_different("\"a${b}\"", "\"a\\${b}\"");
+        assert_different("{ a = 1; }", "rec { a = 1; }");
+        assert_different("{ ${a} = 1; }", "{ a = 1; }");
+        assert_different("\"a\"", "a");
+    }
+
+    #[test]
+    fn fnv1a() {
+        let hash = |s: &str| {
+            let mut hasher = Fnv1a::default();
+            hasher.write(s.as_bytes());
+            hasher.0
+        };
+        assert_eq!(hash(""), 0x6c62272e07bb014262b821756295c58d);
+        assert_eq!(hash("a"), 0xd228cb696f1a8caf78912b704e4a8964);
+    }
+}
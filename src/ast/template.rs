@@ -0,0 +1,368 @@
+//! Build nodes from Nix code with placeholders, like
+//! `{ inherit $name; src = $src; }`.
+
+use std::fmt;
+
+use rowan::{ast::AstNode, TextRange, TextSize};
+
+use crate::{
+    ast::{self, make::operand_at},
+    parser::ParseError,
+    tokenizer::tokenize_ranged,
+    NixLanguage, SyntaxKind,
+    SyntaxKind::*,
+    SyntaxNode,
+};
+
+/// A node to put in place of a placeholder in a template
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Splice {
+    Expr(ast::Expr),
+    Attr(ast::Attr),
+}
+
+impl From<ast::Expr> for Splice {
+    fn from(expr: ast::Expr) -> Self {
+        Splice::Expr(expr)
+    }
+}
+
+impl From<ast::Attr> for Splice {
+    fn from(attr: ast::Attr) -> Self {
+        Splice::Attr(attr)
+    }
+}
+
+impl Splice {
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Splice::Expr(expr) => expr.syntax(),
+            Splice::Attr(attr) => attr.syntax(),
+        }
+    }
+}
+
+/// An error building a node from a template. Ranges are in the template.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum TemplateError {
+    /// The template isn't valid Nix code
+    Parse(ParseError),
+    /// There is no argument for a placeholder
+    MissingArgument(TextRange, String),
+    /// No placeholder uses an argument
+    UnusedArgument(String),
+    /// The argument for a placeholder, of the given kind, can't be used at
+    /// its position, where the described node is expected
+    Misplaced(TextRange, String, SyntaxKind, &'static str),
+    /// The template is a node of a different kind than the one requested
+    UnexpectedKind(SyntaxKind),
+    /// A placeholder runs into the code next to it, like in `a$x` or `$x$y`
+    Adjacent(TextRange, String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Parse(err) => write!(f, "invalid template: {}", err),
+            TemplateError::MissingArgument(range, name) => write!(
+                f,
+                "no argument for `${}` at {}..{}",
+                name,
+                usize::from(range.start()),
+                usize::from(range.end())
+            ),
+            TemplateError::UnusedArgument(name) => {
+                write!(f, "argument `{}` isn't used by the template", name)
+            }
+            TemplateError::Misplaced(range, name, kind, expected) => write!(
+                f,
+                "`${}` at {}..{} is a {}, but {} is expected there",
+                name,
+                usize::from(range.start()),
+                usize::from(range.end()),
+                kind,
+                expected
+            ),
+            TemplateError::UnexpectedKind(kind) => write!(f, "the template is a {}", kind),
+            TemplateError::Adjacent(range, name) => write!(
+                f,
+                "`${}` at {}..{} must be separated from the code next to it",
+                name,
+                usize::from(range.start()),
+                usize::from(range.end())
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// A placeholder in the template, and where it ended up in the code that
+/// the template is parsed as
+struct Placeholder<'a> {
+    name: &'a str,
+    range: TextRange,
+    ident_range: TextRange,
+}
+
+/// Parse `template` as a node of type `N`, replacing placeholders like
+/// `$name` with the nodes in `args`.
+///
+/// Placeholders can stand for expressions, attributes and the names of
+/// function arguments. Expressions are put in parentheses where needed.
+/// Inside strings, use an interpolation like `"${$name}"`.
+///
+/// ```
+/// use rnix::ast::{self, make, template};
+///
+/// let set: ast::AttrSet = template(
+///     "{ inherit $name; src = $src; }",
+///     &[("name", make::attr("hello").into()), ("src", ast::Expr::from(make::path("./src")).into())],
+/// )
+/// .unwrap();
+/// assert_eq!(set.to_string(), "{ inherit hello; src = ./src; }");
+/// ```
+pub fn template<N: AstNode<Language = NixLanguage>>(
+    template: &str,
+    args: &[(&str, Splice)],
+) -> Result<N, TemplateError> {
+    // Replace every placeholder with an identifier, to find out where it is
+    let mut code = String::new();
+    let mut placeholders = Vec::new();
+    let tokens = tokenize_ranged(template);
+    let mut tokens = tokens.iter().peekable();
+    while let Some(token) = tokens.next() {
+        let start = TextSize::of(code.as_str());
+        match tokens.peek() {
+            Some(next)
+                if token.kind == TOKEN_ERROR
+                    && token.text == "$"
+                    && next.kind == TOKEN_IDENT
+                    && next.range.start() == token.range.end() =>
+            {
+                code.push_str(&placeholder_ident(next.text));
+                placeholders.push(Placeholder {
+                    name: next.text,
+                    range: token.range.cover(next.range),
+                    ident_range: TextRange::new(start, TextSize::of(code.as_str())),
+                });
+                tokens.next();
+            }
+            _ => code.push_str(token.text),
+        }
+    }
+
+    // An identifier or number next to the placeholder would run into its name
+    // or into the spliced code
+    let idents: Vec<_> = tokenize_ranged(&code)
+        .into_iter()
+        .filter(|token| token.kind == TOKEN_IDENT)
+        .map(|token| token.range)
+        .collect();
+    if let Some(placeholder) = placeholders.iter().find(|p| {
+        let before = template[..usize::from(p.range.start())].chars().next_back();
+        !idents.contains(&p.ident_range)
+            || before.is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '\''))
+    }) {
+        return Err(TemplateError::Adjacent(placeholder.range, placeholder.name.to_string()));
+    }
+
+    let spans: Vec<_> = placeholders.iter().map(|p| (p.ident_range, p.range)).collect();
+    let parse = crate::Root::parse(&code);
+    if let Some(err) = parse.errors().first() {
+        return Err(TemplateError::Parse(original_error(&spans, err)));
+    }
+    let root = parse.syntax();
+
+    for (name, _) in args {
+        if !placeholders.iter().any(|p| p.name == *name) {
+            return Err(TemplateError::UnusedArgument(name.to_string()));
+        }
+    }
+
+    // Put the text of the arguments in place of the identifiers
+    let mut result = String::new();
+    let mut end = TextSize::from(0);
+    let mut spans = Vec::new();
+    for placeholder in &placeholders {
+        let Some((_, arg)) = args.iter().find(|(name, _)| *name == placeholder.name) else {
+            return Err(TemplateError::MissingArgument(
+                placeholder.range,
+                placeholder.name.to_string(),
+            ));
+        };
+        let node = root
+            .covering_element(placeholder.ident_range)
+            .parent()
+            .filter(|node| node.kind() == NODE_IDENT)
+            .expect("placeholder should be an identifier");
+        let misplaced = |expected| {
+            TemplateError::Misplaced(
+                placeholder.range,
+                placeholder.name.to_string(),
+                arg.syntax().kind(),
+                expected,
+            )
+        };
+
+        let first_child =
+            node.parent().and_then(|parent| parent.first_child()).as_ref() == Some(&node);
+        let text = match node.parent().map(|parent| parent.kind()) {
+            Some(NODE_ATTRPATH | NODE_INHERIT) => match arg {
+                Splice::Attr(attr) => attr.to_string(),
+                Splice::Expr(expr @ (ast::Expr::Ident(_) | ast::Expr::Str(_))) => expr.to_string(),
+                Splice::Expr(_) => return Err(misplaced("an attribute")),
+            },
+            Some(NODE_IDENT_PARAM | NODE_PAT_BIND | NODE_PAT_ENTRY) if first_child => match arg {
+                Splice::Attr(ast::Attr::Ident(ident)) => ident.to_string(),
+                Splice::Expr(ast::Expr::Ident(ident)) => ident.to_string(),
+                _ => return Err(misplaced("an identifier")),
+            },
+            _ => match arg {
+                Splice::Expr(expr) => operand_at(expr.clone(), &node),
+                Splice::Attr(ast::Attr::Ident(ident)) => ident.to_string(),
+                Splice::Attr(ast::Attr::Str(s)) => s.to_string(),
+                Splice::Attr(ast::Attr::Dynamic(_)) => return Err(misplaced("an expression")),
+            },
+        };
+        result.push_str(&code[TextRange::new(end, placeholder.ident_range.start())]);
+        let start = TextSize::of(result.as_str());
+        result.push_str(&text);
+        spans.push((TextRange::new(start, TextSize::of(result.as_str())), placeholder.range));
+        end = placeholder.ident_range.end();
+    }
+    result.push_str(&code[usize::from(end)..]);
+
+    // Spliced nodes can still clash with the template, such as with duplicated attributes
+    let parse = crate::Root::parse(&result);
+    if let Some(err) = parse.errors().first() {
+        return Err(TemplateError::Parse(original_error(&spans, err)));
+    }
+    let expr = parse.tree().expr().expect("template should be an expression");
+    let kind = expr.syntax().kind();
+    N::cast(expr.syntax().clone_subtree()).ok_or(TemplateError::UnexpectedKind(kind))
+}
+
+fn placeholder_ident(name: &str) -> String {
+    format!("__template_{name}")
+}
+
+/// Map the ranges of an error in generated code back to the template, given
+/// the ranges that placeholders took up in both. A range that starts or ends
+/// inside a placeholder covers all of it.
+fn original_error(spans: &[(TextRange, TextRange)], err: &ParseError) -> ParseError {
+    err.map_range(|range| {
+        TextRange::new(
+            original_offset(spans, range.start(), false),
+            original_offset(spans, range.end(), true),
+        )
+    })
+}
+
+fn original_offset(spans: &[(TextRange, TextRange)], offset: TextSize, end: bool) -> TextSize {
+    let mut original = offset;
+    for &(generated, template) in spans {
+        if generated.start() >= offset {
+            break;
+        }
+        if generated.end() > offset {
+            return if end { template.end() } else { template.start() };
+        }
+        original = offset - generated.end() + template.end();
+    }
+    original
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::{ast::AstNode, TextRange};
+
+    use super::{template, Splice, TemplateError};
+    use crate::{
+        ast::{self, make},
+        parser::ParseError,
+        SyntaxKind::*,
+    };
+
+    fn id(name: &str) -> Splice {
+        ast::Expr::from(make::ident(name)).into()
+    }
+
+    #[test]
+    fn splices() {
+        let sum: ast::Expr = make::bin_op(make::int(1), ast::BinOpKind::Add, make::int(2)).into();
+        let expr: ast::Expr = template(
+            "{ inherit $name; x = $sum * 2; s = \"$sum ${$name}\"; f = $x: [ $sum ]; }",
+            &[("name", make::attr("a b").into()), ("sum", sum.into()), ("x", id("x"))],
+        )
+        .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "{ inherit \"a b\"; x = (1 + 2) * 2; s = \"$sum ${\"a b\"}\"; f = x: [ (1 + 2) ]; }"
+        );
+        assert_eq!(expr.syntax().text_range().start(), 0.into());
+
+        let apply: ast::Apply = template("$f $x", &[("f", id("f")), ("x", id("y"))]).unwrap();
+        assert_eq!(apply.to_string(), "f y");
+    }
+
+    #[test]
+    fn errors() {
+        let list: ast::Expr = make::list([]).into();
+        assert_eq!(
+            template::<ast::Expr>("{ a.$x = 1; }", &[("x", list.into())]),
+            Err(TemplateError::Misplaced(
+                TextRange::new(4.into(), 6.into()),
+                "x".into(),
+                NODE_LIST,
+                "an attribute"
+            ))
+        );
+        assert_eq!(
+            template::<ast::Expr>("{ $x, $y }: $x", &[("x", id("x"))]),
+            Err(TemplateError::MissingArgument(TextRange::new(6.into(), 8.into()), "y".into()))
+        );
+        assert_eq!(
+            template::<ast::Expr>("1", &[("x", id("x"))]),
+            Err(TemplateError::UnusedArgument("x".into()))
+        );
+        assert_eq!(
+            template::<ast::List>("$x", &[("x", id("x"))]),
+            Err(TemplateError::UnexpectedKind(NODE_IDENT))
+        );
+        let Err(TemplateError::Parse(err)) = template::<ast::Expr>("[ $x ) ]", &[]) else {
+            panic!("the template should be invalid");
+        };
+        assert_eq!(err.range(), Some(TextRange::new(5.into(), 6.into())));
+
+        // Errors caused by spliced nodes point into the template
+        let Err(TemplateError::Parse(err)) =
+            template::<ast::Expr>("{ $a = 1; b = 2; }", &[("a", make::attr("b").into())])
+        else {
+            panic!("the attribute should be duplicated");
+        };
+        assert_eq!(
+            err,
+            ParseError::DuplicatedAttr(
+                TextRange::new(10.into(), 11.into()),
+                TextRange::new(2.into(), 4.into()),
+                "b".into()
+            )
+        );
+    }
+
+    #[test]
+    fn adjacent() {
+        for (code, range) in [("a$x", (1, 3)), ("$x$y", (0, 2)), ("[ 1$x ]", (3, 5))] {
+            assert_eq!(
+                template::<ast::Expr>(code, &[]),
+                Err(TemplateError::Adjacent(
+                    TextRange::new(range.0.into(), range.1.into()),
+                    "x".into()
+                )),
+                "{code}"
+            );
+        }
+    }
+}
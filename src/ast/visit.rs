@@ -0,0 +1,501 @@
+//! Typed traversal of the AST: a `Visitor` with one method per node type, and
+//! a preorder iterator over expressions
+
+use std::vec;
+
+use rowan::{ast::AstNode as OtherAstNode, WalkEvent};
+
+use crate::ast::{self, Expr, HasEntry, InterpolPart};
+
+/// Visits the nodes of the AST in source order. Every `visit_*` method calls
+/// the matching `walk_*` function by default, which visits the node's
+/// children. Override a method to act on a type of node, and call the `walk_*`
+/// function from it to keep going into the node's children.
+pub trait Visitor {
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+    fn visit_attr(&mut self, attr: &ast::Attr) {
+        walk_attr(self, attr)
+    }
+    fn visit_entry(&mut self, entry: &ast::Entry) {
+        walk_entry(self, entry)
+    }
+    fn visit_param(&mut self, param: &ast::Param) {
+        walk_param(self, param)
+    }
+
+    fn visit_apply(&mut self, node: &ast::Apply) {
+        walk_apply(self, node)
+    }
+    fn visit_assert(&mut self, node: &ast::Assert) {
+        walk_assert(self, node)
+    }
+    fn visit_attrpath(&mut self, node: &ast::Attrpath) {
+        walk_attrpath(self, node)
+    }
+    fn visit_attrpath_value(&mut self, node: &ast::AttrpathValue) {
+        walk_attrpath_value(self, node)
+    }
+    fn visit_attr_set(&mut self, node: &ast::AttrSet) {
+        walk_attr_set(self, node)
+    }
+    fn visit_bin_op(&mut self, node: &ast::BinOp) {
+        walk_bin_op(self, node)
+    }
+    fn visit_dynamic(&mut self, node: &ast::Dynamic) {
+        walk_dynamic(self, node)
+    }
+    fn visit_error(&mut self, node: &ast::Error) {
+        walk_error(self, node)
+    }
+    fn visit_has_attr(&mut self, node: &ast::HasAttr) {
+        walk_has_attr(self, node)
+    }
+    fn visit_ident(&mut self, _node: &ast::Ident) {}
+    fn visit_ident_param(&mut self, node: &ast::IdentParam) {
+        walk_ident_param(self, node)
+    }
+    fn visit_if_else(&mut self, node: &ast::IfElse) {
+        walk_if_else(self, node)
+    }
+    fn visit_inherit(&mut self, node: &ast::Inherit) {
+        walk_inherit(self, node)
+    }
+    fn visit_inherit_from(&mut self, node: &ast::InheritFrom) {
+        walk_inherit_from(self, node)
+    }
+    fn visit_interpol(&mut self, node: &ast::Interpol) {
+        walk_interpol(self, node)
+    }
+    fn visit_lambda(&mut self, node: &ast::Lambda) {
+        walk_lambda(self, node)
+    }
+    fn visit_legacy_let(&mut self, node: &ast::LegacyLet) {
+        walk_legacy_let(self, node)
+    }
+    fn visit_let_in(&mut self, node: &ast::LetIn) {
+        walk_let_in(self, node)
+    }
+    fn visit_list(&mut self, node: &ast::List) {
+        walk_list(self, node)
+    }
+    fn visit_literal(&mut self, _node: &ast::Literal) {}
+    fn visit_paren(&mut self, node: &ast::Paren) {
+        walk_paren(self, node)
+    }
+    fn visit_path(&mut self, node: &ast::Path) {
+        walk_path(self, node)
+    }
+    fn visit_pat_bind(&mut self, node: &ast::PatBind) {
+        walk_pat_bind(self, node)
+    }
+    fn visit_pat_entry(&mut self, node: &ast::PatEntry) {
+        walk_pat_entry(self, node)
+    }
+    fn visit_pattern(&mut self, node: &ast::Pattern) {
+        walk_pattern(self, node)
+    }
+    fn visit_root(&mut self, node: &ast::Root) {
+        walk_root(self, node)
+    }
+    fn visit_select(&mut self, node: &ast::Select) {
+        walk_select(self, node)
+    }
+    fn visit_str(&mut self, node: &ast::Str) {
+        walk_str(self, node)
+    }
+    fn visit_unary_op(&mut self, node: &ast::UnaryOp) {
+        walk_unary_op(self, node)
+    }
+    fn visit_with(&mut self, node: &ast::With) {
+        walk_with(self, node)
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Apply(it) => v.visit_apply(it),
+        Expr::Assert(it) => v.visit_assert(it),
+        Expr::Error(it) => v.visit_error(it),
+        Expr::IfElse(it) => v.visit_if_else(it),
+        Expr::Select(it) => v.visit_select(it),
+        Expr::Str(it) => v.visit_str(it),
+        Expr::Path(it) => v.visit_path(it),
+        Expr::Literal(it) => v.visit_literal(it),
+        Expr::Lambda(it) => v.visit_lambda(it),
+        Expr::LegacyLet(it) => v.visit_legacy_let(it),
+        Expr::LetIn(it) => v.visit_let_in(it),
+        Expr::List(it) => v.visit_list(it),
+        Expr::BinOp(it) => v.visit_bin_op(it),
+        Expr::Paren(it) => v.visit_paren(it),
+        Expr::Root(it) => v.visit_root(it),
+        Expr::AttrSet(it) => v.visit_attr_set(it),
+        Expr::UnaryOp(it) => v.visit_unary_op(it),
+        Expr::Ident(it) => v.visit_ident(it),
+        Expr::With(it) => v.visit_with(it),
+        Expr::HasAttr(it) => v.visit_has_attr(it),
+    }
+}
+
+pub fn walk_attr<V: Visitor + ?Sized>(v: &mut V, attr: &ast::Attr) {
+    match attr {
+        ast::Attr::Ident(it) => v.visit_ident(it),
+        ast::Attr::Dynamic(it) => v.visit_dynamic(it),
+        ast::Attr::Str(it) => v.visit_str(it),
+    }
+}
+
+pub fn walk_entry<V: Visitor + ?Sized>(v: &mut V, entry: &ast::Entry) {
+    match entry {
+        ast::Entry::Inherit(it) => v.visit_inherit(it),
+        ast::Entry::AttrpathValue(it) => v.visit_attrpath_value(it),
+    }
+}
+
+pub fn walk_param<V: Visitor + ?Sized>(v: &mut V, param: &ast::Param) {
+    match param {
+        ast::Param::Pattern(it) => v.visit_pattern(it),
+        ast::Param::IdentParam(it) => v.visit_ident_param(it),
+    }
+}
+
+fn visit_opt_expr<V: Visitor + ?Sized>(v: &mut V, expr: Option<Expr>) {
+    if let Some(expr) = expr {
+        v.visit_expr(&expr);
+    }
+}
+
+fn visit_entries<V: Visitor + ?Sized>(v: &mut V, node: &impl HasEntry) {
+    for entry in node.entries() {
+        v.visit_entry(&entry);
+    }
+}
+
+fn visit_interpols<V: Visitor + ?Sized, T>(
+    v: &mut V,
+    parts: impl Iterator<Item = InterpolPart<T>>,
+) {
+    for part in parts {
+        if let InterpolPart::Interpolation(interpol) = part {
+            v.visit_interpol(&interpol);
+        }
+    }
+}
+
+pub fn walk_apply<V: Visitor + ?Sized>(v: &mut V, node: &ast::Apply) {
+    visit_opt_expr(v, node.lambda());
+    visit_opt_expr(v, node.argument());
+}
+
+pub fn walk_assert<V: Visitor + ?Sized>(v: &mut V, node: &ast::Assert) {
+    visit_opt_expr(v, node.condition());
+    visit_opt_expr(v, node.body());
+}
+
+pub fn walk_attrpath<V: Visitor + ?Sized>(v: &mut V, node: &ast::Attrpath) {
+    for attr in node.attrs() {
+        v.visit_attr(&attr);
+    }
+}
+
+pub fn walk_attrpath_value<V: Visitor + ?Sized>(v: &mut V, node: &ast::AttrpathValue) {
+    if let Some(attrpath) = node.attrpath() {
+        v.visit_attrpath(&attrpath);
+    }
+    visit_opt_expr(v, node.value());
+}
+
+pub fn walk_attr_set<V: Visitor + ?Sized>(v: &mut V, node: &ast::AttrSet) {
+    visit_entries(v, node);
+}
+
+pub fn walk_bin_op<V: Visitor + ?Sized>(v: &mut V, node: &ast::BinOp) {
+    visit_opt_expr(v, node.lhs());
+    visit_opt_expr(v, node.rhs());
+}
+
+pub fn walk_dynamic<V: Visitor + ?Sized>(v: &mut V, node: &ast::Dynamic) {
+    visit_opt_expr(v, node.expr());
+}
+
+/// Error nodes can contain anything, so this visits all expressions directly below them
+pub fn walk_error<V: Visitor + ?Sized>(v: &mut V, node: &ast::Error) {
+    for child in node.syntax().children().filter_map(Expr::cast) {
+        v.visit_expr(&child);
+    }
+}
+
+pub fn walk_has_attr<V: Visitor + ?Sized>(v: &mut V, node: &ast::HasAttr) {
+    visit_opt_expr(v, node.expr());
+    if let Some(attrpath) = node.attrpath() {
+        v.visit_attrpath(&attrpath);
+    }
+}
+
+pub fn walk_ident_param<V: Visitor + ?Sized>(v: &mut V, node: &ast::IdentParam) {
+    if let Some(ident) = node.ident() {
+        v.visit_ident(&ident);
+    }
+}
+
+pub fn walk_if_else<V: Visitor + ?Sized>(v: &mut V, node: &ast::IfElse) {
+    visit_opt_expr(v, node.condition());
+    visit_opt_expr(v, node.body());
+    visit_opt_expr(v, node.else_body());
+}
+
+pub fn walk_inherit<V: Visitor + ?Sized>(v: &mut V, node: &ast::Inherit) {
+    if let Some(from) = node.from() {
+        v.visit_inherit_from(&from);
+    }
+    for attr in node.attrs() {
+        v.visit_attr(&attr);
+    }
+}
+
+pub fn walk_inherit_from<V: Visitor + ?Sized>(v: &mut V, node: &ast::InheritFrom) {
+    visit_opt_expr(v, node.expr());
+}
+
+pub fn walk_interpol<V: Visitor + ?Sized>(v: &mut V, node: &ast::Interpol) {
+    visit_opt_expr(v, node.expr());
+}
+
+pub fn walk_lambda<V: Visitor + ?Sized>(v: &mut V, node: &ast::Lambda) {
+    if let Some(param) = node.param() {
+        v.visit_param(&param);
+    }
+    visit_opt_expr(v, node.body());
+}
+
+pub fn walk_legacy_let<V: Visitor + ?Sized>(v: &mut V, node: &ast::LegacyLet) {
+    visit_entries(v, node);
+}
+
+pub fn walk_let_in<V: Visitor + ?Sized>(v: &mut V, node: &ast::LetIn) {
+    visit_entries(v, node);
+    visit_opt_expr(v, node.body());
+}
+
+pub fn walk_list<V: Visitor + ?Sized>(v: &mut V, node: &ast::List) {
+    for item in node.items() {
+        v.visit_expr(&item);
+    }
+}
+
+pub fn walk_paren<V: Visitor + ?Sized>(v: &mut V, node: &ast::Paren) {
+    visit_opt_expr(v, node.expr());
+}
+
+pub fn walk_path<V: Visitor + ?Sized>(v: &mut V, node: &ast::Path) {
+    visit_interpols(v, node.parts());
+}
+
+pub fn walk_pat_bind<V: Visitor + ?Sized>(v: &mut V, node: &ast::PatBind) {
+    if let Some(ident) = node.ident() {
+        v.visit_ident(&ident);
+    }
+}
+
+pub fn walk_pat_entry<V: Visitor + ?Sized>(v: &mut V, node: &ast::PatEntry) {
+    if let Some(ident) = node.ident() {
+        v.visit_ident(&ident);
+    }
+    visit_opt_expr(v, node.default());
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(v: &mut V, node: &ast::Pattern) {
+    // The binding can come before or after the entries
+    for child in node.syntax().children() {
+        if let Some(entry) = ast::PatEntry::cast(child.clone()) {
+            v.visit_pat_entry(&entry);
+        } else if let Some(bind) = ast::PatBind::cast(child) {
+            v.visit_pat_bind(&bind);
+        }
+    }
+}
+
+pub fn walk_root<V: Visitor + ?Sized>(v: &mut V, node: &ast::Root) {
+    visit_opt_expr(v, node.expr());
+}
+
+pub fn walk_select<V: Visitor + ?Sized>(v: &mut V, node: &ast::Select) {
+    visit_opt_expr(v, node.expr());
+    if let Some(attrpath) = node.attrpath() {
+        v.visit_attrpath(&attrpath);
+    }
+    visit_opt_expr(v, node.default_expr());
+}
+
+pub fn walk_str<V: Visitor + ?Sized>(v: &mut V, node: &ast::Str) {
+    visit_interpols(v, node.parts());
+}
+
+pub fn walk_unary_op<V: Visitor + ?Sized>(v: &mut V, node: &ast::UnaryOp) {
+    visit_opt_expr(v, node.expr());
+}
+
+pub fn walk_with<V: Visitor + ?Sized>(v: &mut V, node: &ast::With) {
+    visit_opt_expr(v, node.namespace());
+    visit_opt_expr(v, node.body());
+}
+
+/// Collects the expressions directly below an expression
+struct ChildExprs(Vec<Expr>);
+
+impl Visitor for ChildExprs {
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.0.push(expr.clone());
+    }
+}
+
+impl Expr {
+    /// The expressions directly below this one, in source order. This
+    /// includes expressions inside of other nodes, such as the values of an
+    /// attribute set, interpolations and the defaults of a pattern.
+    pub fn child_exprs(&self) -> impl Iterator<Item = Expr> {
+        let mut children = ChildExprs(Vec::new());
+        walk_expr(&mut children, self);
+        children.0.into_iter()
+    }
+
+    /// Iterate over this expression and all expressions below it. Every
+    /// expression is entered before its children and left after them.
+    pub fn preorder(&self) -> Preorder {
+        Preorder { start: Some(self.clone()), stack: Vec::new() }
+    }
+}
+
+/// A preorder traversal of expressions, see `Expr::preorder`
+pub struct Preorder {
+    start: Option<Expr>,
+    stack: Vec<(Expr, vec::IntoIter<Expr>)>,
+}
+
+impl Preorder {
+    /// Don't go into the children of the expression that was just entered
+    pub fn skip_subtree(&mut self) {
+        if let Some((_, children)) = self.stack.last_mut() {
+            *children = Vec::new().into_iter();
+        }
+    }
+
+    fn enter(&mut self, expr: Expr) -> WalkEvent<Expr> {
+        let children: Vec<_> = expr.child_exprs().collect();
+        self.stack.push((expr.clone(), children.into_iter()));
+        WalkEvent::Enter(expr)
+    }
+}
+
+impl Iterator for Preorder {
+    type Item = WalkEvent<Expr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            return Some(self.enter(start));
+        }
+        let (_, children) = self.stack.last_mut()?;
+        match children.next() {
+            Some(child) => Some(self.enter(child)),
+            None => self.stack.pop().map(|(expr, _)| WalkEvent::Leave(expr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::WalkEvent;
+
+    use super::{walk_lambda, Visitor};
+    use crate::{ast, Root};
+
+    #[derive(Default)]
+    struct Idents {
+        idents: Vec<String>,
+        lambdas: usize,
+    }
+
+    impl Visitor for Idents {
+        fn visit_ident(&mut self, node: &ast::Ident) {
+            self.idents.push(node.to_string());
+        }
+        fn visit_lambda(&mut self, node: &ast::Lambda) {
+            self.lambdas += 1;
+            walk_lambda(self, node);
+        }
+    }
+
+    const CODE: &str =
+        r#"{ a, b ? c }@d: let e = f: "${g}"; inherit (h) i; in { j.${k} = [ l (m n) ]; } // o"#;
+
+    #[test]
+    fn visitor() {
+        let root = Root::parse(CODE).tree();
+        let mut visitor = Idents::default();
+        visitor.visit_root(&root);
+        assert_eq!(
+            visitor.idents,
+            ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o"]
+        );
+        assert_eq!(visitor.lambdas, 2);
+    }
+
+    #[test]
+    fn child_exprs() {
+        let root = Root::parse(CODE).tree();
+        let lambda = root.expr().unwrap();
+        let children: Vec<_> = lambda.child_exprs().map(|expr| expr.to_string()).collect();
+        assert_eq!(children, ["c", &CODE[16..]]);
+
+        let let_in = lambda.child_exprs().nth(1).unwrap();
+        let children: Vec<_> = let_in.child_exprs().map(|expr| expr.to_string()).collect();
+        assert_eq!(children, ["f: \"${g}\"", "h", "{ j.${k} = [ l (m n) ]; } // o"]);
+    }
+
+    #[test]
+    fn preorder() {
+        let root = Root::parse("[ (a b) { c = d; } ]").tree();
+        let events: Vec<_> = ast::Expr::Root(root)
+            .preorder()
+            .map(|event| match event {
+                WalkEvent::Enter(expr) => format!("> {}", expr),
+                WalkEvent::Leave(expr) => format!("< {}", expr),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "> [ (a b) { c = d; } ]",
+                "> [ (a b) { c = d; } ]",
+                "> (a b)",
+                "> a b",
+                "> a",
+                "< a",
+                "> b",
+                "< b",
+                "< a b",
+                "< (a b)",
+                "> { c = d; }",
+                "> d",
+                "< d",
+                "< { c = d; }",
+                "< [ (a b) { c = d; } ]",
+                "< [ (a b) { c = d; } ]",
+            ]
+        );
+
+        let mut preorder = Root::parse("(a b) c").tree().expr().unwrap().preorder();
+        let mut entered = Vec::new();
+        while let Some(event) = preorder.next() {
+            if let WalkEvent::Enter(expr) = event {
+                entered.push(expr.to_string());
+                if matches!(expr, ast::Expr::Paren(_)) {
+                    preorder.skip_subtree();
+                }
+            }
+        }
+        assert_eq!(entered, ["(a b) c", "(a b)", "c"]);
+        assert!(preorder.next().is_none());
+    }
+}
@@ -0,0 +1,453 @@
+//! Diagnostics: errors and warnings with codes, positions, labels and
+//! suggested fixes, ready to be shown in an editor or a CI report
+
+use std::fmt;
+
+use rowan::{NodeOrToken, TextRange, TextSize};
+
+use crate::{
+    parser::ParseError,
+    Parse,
+    SyntaxKind::{self, *},
+    SyntaxNode, SyntaxToken,
+};
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem in the source, such as a parse error
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// A stable identifier for the kind of problem, such as `E003`
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is
+    pub range: TextRange,
+    /// Other places that help explain the problem
+    pub labels: Vec<Label>,
+    /// Edits that fix the problem, each of them on its own
+    pub fixes: Vec<Fix>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}] at {}..{}: {}",
+            self.severity,
+            self.code,
+            usize::from(self.range.start()),
+            usize::from(self.range.end()),
+            self.message
+        )
+    }
+}
+
+/// A message attached to another part of the source
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub range: TextRange,
+    pub message: String,
+}
+
+/// A suggested fix, made up of one or more edits
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Replace the text in `range` with `insert`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: TextRange,
+    pub insert: String,
+}
+
+impl<T> Parse<T> {
+    /// Turn the errors of this parse into diagnostics. The parser usually
+    /// reports the end of the file more than once, only the first of those
+    /// errors is kept.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let root = self.syntax();
+        let unclosed = unclosed_delimiters(&root);
+
+        let mut seen_eof = false;
+        let mut diagnostics = Vec::new();
+        for err in &self.errors {
+            if matches!(err, ParseError::UnexpectedEOF | ParseError::UnexpectedEOFWanted(_)) {
+                if seen_eof {
+                    continue;
+                }
+                seen_eof = true;
+            }
+            let first = diagnostics.is_empty();
+            diagnostics.push(diagnostic(&root, &unclosed, err, first));
+        }
+        diagnostics.extend(self.warnings());
+        diagnostics
+    }
+
+    /// Deprecated syntax that Nix still accepts. Syntax that is turned off in
+    /// the `ParseOptions` is reported as an error instead.
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        let options = self.options;
+        self.syntax()
+            .descendants_with_tokens()
+            .filter_map(|element| match element {
+                NodeOrToken::Node(node) => match node.kind() {
+                    NODE_LEGACY_LET if options.legacy_let => Some(legacy_let(&node)),
+                    NODE_IDENT
+                        if options.or_as_ident
+                            && node.parent().is_some_and(|parent| parent.kind() == NODE_APPLY)
+                            && node.text() == "or" =>
+                    {
+                        Some(or_as_ident(&node))
+                    }
+                    _ => None,
+                },
+                NodeOrToken::Token(token) => match token.kind() {
+                    TOKEN_URI
+                        if options.uri_literals
+                            && token
+                                .parent()
+                                .is_some_and(|parent| parent.kind() == NODE_LITERAL) =>
+                    {
+                        Some(uri_literal(&token))
+                    }
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+}
+
+fn warning(code: &'static str, message: &str, range: TextRange, fixes: Vec<Fix>) -> Diagnostic {
+    Diagnostic {
+        code,
+        severity: Severity::Warning,
+        message: message.to_string(),
+        range,
+        labels: Vec::new(),
+        fixes,
+    }
+}
+
+/// `let { a = 1; body = a; }` is the same as `(rec { a = 1; body = a; }).body`
+fn legacy_let(node: &SyntaxNode) -> Diagnostic {
+    let range = node.text_range();
+    let keyword = node.first_token().filter(|token| token.kind() == T![let]);
+    let fixes = keyword.map(|keyword| Fix {
+        message: "rewrite as `(rec { ... }).body`".to_string(),
+        edits: vec![
+            TextEdit { range: keyword.text_range(), insert: "(rec".to_string() },
+            TextEdit { range: TextRange::empty(range.end()), insert: ").body".to_string() },
+        ],
+    });
+    warning("W001", "`let { ... }` is deprecated", range, fixes.into_iter().collect())
+}
+
+fn uri_literal(token: &SyntaxToken) -> Diagnostic {
+    let range = token.text_range();
+    let fix = Fix {
+        message: "quote the URI".to_string(),
+        edits: vec![TextEdit { range, insert: format!("\"{}\"", token.text()) }],
+    };
+    warning("W002", "unquoted URIs are deprecated", range, vec![fix])
+}
+
+/// There is no other way to refer to a variable called `or`, so the only fix
+/// is to rename it
+fn or_as_ident(node: &SyntaxNode) -> Diagnostic {
+    let mut diagnostic = warning(
+        "W003",
+        "using `or` as an identifier is deprecated, rename the variable",
+        node.text_range(),
+        Vec::new(),
+    );
+    diagnostic.labels.push(Label {
+        range: node.text_range(),
+        message: "this is a variable called `or`, not a default for a missing attribute"
+            .to_string(),
+    });
+    diagnostic
+}
+
+/// A delimiter like `{` or `"` whose closing counterpart is missing
+struct Unclosed {
+    open: SyntaxKind,
+    open_range: TextRange,
+    close: SyntaxKind,
+    close_text: String,
+    /// Nothing but trivia follows the delimited node
+    at_end: bool,
+}
+
+impl Unclosed {
+    fn label(&self) -> Label {
+        Label { range: self.open_range, message: format!("this {} was never closed", self.open) }
+    }
+}
+
+fn unclosed_delimiters(root: &SyntaxNode) -> Vec<Unclosed> {
+    root.descendants()
+        .filter_map(|node| {
+            let (open, close) = delimiters(node.kind())?;
+            let mut tokens = node.children_with_tokens().filter_map(|child| child.into_token());
+            let open_token = tokens.find(|token| token.kind() == open)?;
+            if tokens.any(|token| token.kind() == close) {
+                return None;
+            }
+            let close_text = match close {
+                TOKEN_STRING_END => open_token.text().to_string(),
+                TOKEN_INTERPOL_END | T!['}'] => "}".to_string(),
+                T![']'] => "]".to_string(),
+                _ => ")".to_string(),
+            };
+            let mut next = node.last_token().and_then(|token| token.next_token());
+            while let Some(token) = next.as_ref().filter(|token| token.kind().is_trivia()) {
+                next = token.next_token();
+            }
+            Some(Unclosed {
+                open,
+                open_range: open_token.text_range(),
+                close,
+                close_text,
+                at_end: next.is_none(),
+            })
+        })
+        .collect()
+}
+
+fn delimiters(kind: SyntaxKind) -> Option<(SyntaxKind, SyntaxKind)> {
+    match kind {
+        NODE_ATTR_SET | NODE_LEGACY_LET | NODE_PATTERN => Some((T!['{'], T!['}'])),
+        NODE_LIST => Some((T!['['], T![']'])),
+        NODE_PAREN | NODE_INHERIT_FROM => Some((T!['('], T![')'])),
+        NODE_STRING => Some((TOKEN_STRING_START, TOKEN_STRING_END)),
+        NODE_INTERPOL | NODE_DYNAMIC => Some((TOKEN_INTERPOL_START, TOKEN_INTERPOL_END)),
+        _ => None,
+    }
+}
+
+/// `first` is false if there were errors before this one. An error at the
+/// end of the file is often caused by them, so fixing the end won't help.
+fn diagnostic(
+    root: &SyntaxNode,
+    unclosed: &[Unclosed],
+    err: &ParseError,
+    first: bool,
+) -> Diagnostic {
+    let end = root.text_range().end();
+    let eof = TextRange::empty(end);
+    let found = |range: TextRange| {
+        root.token_at_offset(range.start())
+            .right_biased()
+            .map_or_else(|| "end of file".to_string(), |token| token.kind().to_string())
+    };
+
+    let mut labels = Vec::new();
+    let mut fixes = Vec::new();
+    let (code, message, range) = match err {
+        ParseError::Unexpected(range) => ("E001", format!("unexpected {}", found(*range)), *range),
+        ParseError::UnexpectedExtra(range) => {
+            ("E002", format!("unexpected {}, expected end of file", found(*range)), *range)
+        }
+        ParseError::UnexpectedWanted(got, range, wanted) => {
+            // Point at the delimiter the wanted token would have closed
+            let open = unclosed
+                .iter()
+                .filter(|open| {
+                    wanted.contains(&open.close) && open.open_range.start() < range.start()
+                })
+                .max_by_key(|open| open.open_range.start());
+            labels.extend(open.map(Unclosed::label));
+            if wanted.contains(&T![;]) {
+                let at = end_of_previous_token(root, range.start());
+                fixes.push(insert("`;`", at, ";"));
+            }
+            ("E003", format!("expected {}, found {}", one_of(wanted), got), *range)
+        }
+        ParseError::UnexpectedDoubleBind(range) => {
+            ("E004", "a pattern can only be bound to one name".to_string(), *range)
+        }
+        ParseError::UnexpectedEOF | ParseError::UnexpectedEOFWanted(_) => {
+            let (code, message, wanted): (_, _, &[SyntaxKind]) = match err {
+                ParseError::UnexpectedEOFWanted(wanted) => {
+                    ("E006", format!("expected {}, found end of file", one_of(wanted)), wanted)
+                }
+                _ => ("E005", "unexpected end of file".to_string(), &[]),
+            };
+            let open: Vec<_> = unclosed.iter().filter(|open| open.at_end).collect();
+            labels.extend(open.iter().map(|open| open.label()));
+
+            let mut text = String::new();
+            if wanted.contains(&T![;]) {
+                text.push(';');
+            }
+            for open in open.iter().rev() {
+                text.push_str(&open.close_text);
+            }
+            if first && !text.is_empty() {
+                fixes.push(insert(&format!("`{}`", text), end, &text));
+            }
+            (code, message, eof)
+        }
+        ParseError::DuplicatedArgs(range, name) => {
+            ("E007", format!("duplicated function argument `{}`", name), *range)
+        }
+        ParseError::RecursionLimitExceeded => {
+            ("E008", "the expression is nested too deeply".to_string(), root.text_range())
+        }
+        ParseError::UnsupportedSyntax(range, feature) => {
+            ("E009", format!("{} is not supported", feature), *range)
+        }
+        ParseError::InvalidToken(range, reason) => ("E011", reason.to_string(), *range),
+        ParseError::DuplicatedAttr(range, first, path) => {
+            labels.push(Label { range: *first, message: "first defined here".to_string() });
+            ("E010", format!("attribute `{}` is already defined", path), *range)
+        }
+        ParseError::InvalidLiteral(range, reason) => ("E012", reason.to_string(), *range),
+    };
+
+    Diagnostic { code, severity: Severity::Error, message, range, labels, fixes }
+}
+
+fn insert(what: &str, at: TextSize, text: &str) -> Fix {
+    Fix {
+        message: format!("insert {}", what),
+        edits: vec![TextEdit { range: TextRange::empty(at), insert: text.to_string() }],
+    }
+}
+
+/// The end of the last token before `offset` that isn't trivia, where
+/// missing tokens should be inserted
+fn end_of_previous_token(root: &SyntaxNode, offset: TextSize) -> TextSize {
+    let mut token = root.token_at_offset(offset).left_biased();
+    while let Some(t) = token.as_ref().filter(|t| t.kind().is_trivia()) {
+        token = t.prev_token();
+    }
+    token.map_or(offset, |token| token.text_range().end())
+}
+
+/// Format a list of kinds as "`a`, `b` or `c`"
+fn one_of(kinds: &[SyntaxKind]) -> String {
+    let names: Vec<_> = kinds.iter().map(|kind| kind.to_string()).collect();
+    match names.split_last() {
+        None => "nothing".to_string(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::TextRange;
+
+    use super::{Fix, Label, Severity, TextEdit};
+    use crate::{parser::ParseOptions, Root};
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::new(start.into(), end.into())
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        let diagnostics = Root::parse("let a = 1; b = 2 in b").diagnostics();
+        // The parser keeps looking for the `;` until the end of the file, but
+        // that error is only a consequence of the first one
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[1].fixes.is_empty());
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.code, "E003");
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.to_string(), "error[E003] at 17..21: expected `;`, found `in`");
+        assert_eq!(
+            diagnostic.fixes,
+            [Fix {
+                message: "insert `;`".into(),
+                edits: vec![TextEdit { range: range(16, 16), insert: ";".into() }]
+            }]
+        );
+    }
+
+    #[test]
+    fn unclosed_delimiters() {
+        let diagnostics = Root::parse("{ a = [ 1 (f x").diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.code, "E005");
+        assert_eq!(diagnostic.range, range(14, 14));
+        assert_eq!(
+            diagnostic.labels,
+            [
+                Label { range: range(0, 1), message: "this `{` was never closed".into() },
+                Label { range: range(6, 7), message: "this `[` was never closed".into() },
+                Label { range: range(10, 11), message: "this `(` was never closed".into() },
+            ]
+        );
+        assert_eq!(diagnostic.fixes[0].edits[0].insert, ")]}");
+    }
+
+    #[test]
+    fn deprecated_syntax() {
+        let parse = Root::parse("let { body = f or https://nixos.org; }");
+        assert!(parse.errors().is_empty());
+        let warnings = parse.warnings();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings.iter().all(|warning| warning.severity == Severity::Warning));
+        assert_eq!(parse.diagnostics(), warnings);
+
+        assert_eq!(warnings[0].code, "W001");
+        assert_eq!(warnings[0].range, range(0, 38));
+        assert_eq!(
+            warnings[0].fixes[0].edits,
+            [
+                TextEdit { range: range(0, 3), insert: "(rec".into() },
+                TextEdit { range: range(38, 38), insert: ").body".into() },
+            ]
+        );
+        assert_eq!(warnings[1].code, "W003");
+        assert_eq!(warnings[1].range, range(15, 17));
+        assert!(warnings[1].fixes.is_empty());
+        assert_eq!(warnings[2].code, "W002");
+        assert_eq!(
+            warnings[2].fixes[0].edits,
+            [TextEdit { range: range(18, 35), insert: "\"https://nixos.org\"".into() }]
+        );
+
+        // Attributes may still be called `or`
+        assert!(Root::parse("{ or = 1; }.or").warnings().is_empty());
+
+        // Syntax that is turned off is an error instead
+        let options = ParseOptions::default().uri_literals(false);
+        let parse = Root::parse_with("https://nixos.org", options);
+        assert!(parse.warnings().is_empty());
+        assert_eq!(parse.diagnostics()[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn duplicated_attr() {
+        let diagnostics = Root::parse("{ a = 1; a = 2; }").diagnostics();
+        assert_eq!(diagnostics[0].message, "attribute `a` is already defined");
+        assert_eq!(diagnostics[0].range, range(9, 10));
+        assert_eq!(
+            diagnostics[0].labels,
+            [Label { range: range(2, 3), message: "first defined here".into() }]
+        );
+    }
+}
@@ -0,0 +1,667 @@
+//! A desugared representation of Nix code, lowered from the AST.
+//!
+//! Unlike the syntax tree, it owns all of its data and can be shared between
+//! threads. Expressions live in an arena in a [`Module`] and refer to each
+//! other by [`ExprId`]. Parentheses, trivia and errors are gone, and several
+//! constructs are replaced by simpler ones:
+//!
+//! - `inherit a;` becomes a binding of `a` to a reference marked as inherited,
+//!   and `inherit (x) a;` a binding of `a` to `x.a`
+//! - `a.b.c = 1;` becomes `a = { b = { c = 1; }; };`, merged with other
+//!   bindings of `a`
+//! - `let { ...; body = ...; }` becomes `(rec { ...; body = ...; }).body`
+//! - `<nixpkgs>` becomes `__findFile __nixPath "nixpkgs"`
+//! - URIs become strings
+//! - `or` used as an identifier becomes an ordinary reference
+//!
+//! A [`SourceMap`] maps every expression back to the range of the code it
+//! was lowered from, and [`show`] prints an expression the way
+//! `nix-instantiate --parse` does.
+
+mod print;
+
+use std::ops::Index;
+
+use rowan::{ast::AstNode, TextRange};
+
+use crate::{
+    ast::{self, BinOpKind, HasEntry, InterpolPart, LiteralKind, PathKind, UnaryOpKind},
+    SyntaxKind::*,
+};
+
+pub use print::show;
+
+/// The index of an expression in a [`Module`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// A part of the code that is missing or couldn't be parsed
+    Missing,
+    Literal(Literal),
+    String(Vec<StrPart>),
+    /// A path other than a search path, with its interpolations
+    Path(PathKind, Vec<StrPart>),
+    /// A variable
+    Reference(String),
+    Apply {
+        lambda: ExprId,
+        argument: ExprId,
+    },
+    Lambda {
+        param: Param,
+        body: ExprId,
+    },
+    Assert {
+        condition: ExprId,
+        body: ExprId,
+    },
+    IfElse {
+        condition: ExprId,
+        body: ExprId,
+        else_body: ExprId,
+    },
+    Select {
+        set: ExprId,
+        attrpath: Vec<AttrName>,
+        default: Option<ExprId>,
+    },
+    HasAttr {
+        set: ExprId,
+        attrpath: Vec<AttrName>,
+    },
+    AttrSet {
+        rec: bool,
+        bindings: Vec<Binding>,
+    },
+    LetIn {
+        bindings: Vec<Binding>,
+        body: ExprId,
+    },
+    With {
+        namespace: ExprId,
+        body: ExprId,
+    },
+    List(Vec<ExprId>),
+    BinOp {
+        lhs: ExprId,
+        operator: BinOpKind,
+        rhs: ExprId,
+    },
+    UnaryOp {
+        operator: UnaryOpKind,
+        expr: ExprId,
+    },
+}
+
+impl Expr {
+    /// The expressions directly inside this one, including the values of
+    /// bindings and dynamic attribute names
+    pub fn children(&self) -> impl Iterator<Item = ExprId> {
+        let mut children = Vec::new();
+        let attrpath = |children: &mut Vec<ExprId>, attrpath: &[AttrName]| {
+            children.extend(attrpath.iter().filter_map(|name| match name {
+                AttrName::Dynamic(id) => Some(*id),
+                AttrName::Static(_) => None,
+            }))
+        };
+        let bindings = |children: &mut Vec<ExprId>, bindings: &[Binding]| {
+            for binding in bindings {
+                if let AttrName::Dynamic(id) = binding.name {
+                    children.push(id);
+                }
+                children.push(binding.value);
+            }
+        };
+        match self {
+            Expr::Missing | Expr::Literal(_) | Expr::Reference(_) => {}
+            Expr::String(parts) | Expr::Path(_, parts) => {
+                children.extend(parts.iter().filter_map(|part| match part {
+                    StrPart::Interpolation(id) => Some(*id),
+                    StrPart::Literal(_) => None,
+                }))
+            }
+            Expr::Apply { lambda, argument } => children.extend([*lambda, *argument]),
+            Expr::Lambda { param, body } => {
+                if let Param::Pattern { entries, .. } = param {
+                    children.extend(entries.iter().filter_map(|entry| entry.default));
+                }
+                children.push(*body);
+            }
+            Expr::Assert { condition, body } => children.extend([*condition, *body]),
+            Expr::IfElse { condition, body, else_body } => {
+                children.extend([*condition, *body, *else_body])
+            }
+            Expr::Select { set, attrpath: path, default } => {
+                children.push(*set);
+                attrpath(&mut children, path);
+                children.extend(*default);
+            }
+            Expr::HasAttr { set, attrpath: path } => {
+                children.push(*set);
+                attrpath(&mut children, path);
+            }
+            Expr::AttrSet { bindings: b, .. } => bindings(&mut children, b),
+            Expr::LetIn { bindings: b, body } => {
+                bindings(&mut children, b);
+                children.push(*body);
+            }
+            Expr::With { namespace, body } => children.extend([*namespace, *body]),
+            Expr::List(items) => children.extend(items),
+            Expr::BinOp { lhs, rhs, .. } => children.extend([*lhs, *rhs]),
+            Expr::UnaryOp { expr, .. } => children.push(*expr),
+        }
+        children.into_iter()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Literal {
+    Integer(i64),
+    Float(f64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StrPart {
+    Literal(String),
+    Interpolation(ExprId),
+}
+
+/// The name of an attribute in a binding or an attribute path
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AttrName {
+    Static(String),
+    /// A name that depends on an expression, like `${x}` or `"a${x}"`
+    Dynamic(ExprId),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub name: AttrName,
+    pub value: ExprId,
+    pub kind: BindingKind,
+}
+
+/// How a [`Binding`] was written
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BindingKind {
+    /// `a = value;`, or part of a nested attribute path
+    Plain,
+    /// `inherit a;`. The value is a reference that is looked up outside of
+    /// the recursive set or `let` the binding is in.
+    Inherited,
+    /// `inherit (from) a;`. The value selects the name from the source.
+    InheritedFrom,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Param {
+    Ident(String),
+    Pattern { entries: Vec<PatEntry>, ellipsis: bool, bind: Option<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PatEntry {
+    pub name: String,
+    pub default: Option<ExprId>,
+}
+
+/// The expressions of a file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    exprs: Vec<Expr>,
+    root: Option<ExprId>,
+}
+
+impl Module {
+    /// The expression of the whole file
+    pub fn root(&self) -> ExprId {
+        self.root.expect("a lowered module has a root")
+    }
+
+    pub fn exprs(&self) -> impl Iterator<Item = (ExprId, &Expr)> {
+        self.exprs.iter().enumerate().map(|(i, expr)| (ExprId(i as u32), expr))
+    }
+}
+
+impl Index<ExprId> for Module {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.exprs[id.index()]
+    }
+}
+
+/// The ranges of code that the expressions of a [`Module`] come from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    ranges: Vec<TextRange>,
+}
+
+impl SourceMap {
+    pub fn range(&self, id: ExprId) -> TextRange {
+        self.ranges[id.index()]
+    }
+
+    /// The expression lowered from exactly `range`. If there are several,
+    /// like for the parts of a desugared `<nixpkgs>`, the outermost one.
+    pub fn expr_for_range(&self, module: &Module, range: TextRange) -> Option<ExprId> {
+        let matches: Vec<_> = (0..self.ranges.len() as u32)
+            .map(ExprId)
+            .filter(|&id| self.range(id) == range)
+            .collect();
+        matches
+            .iter()
+            .copied()
+            .find(|&id| !matches.iter().any(|&other| module[other].children().any(|c| c == id)))
+    }
+}
+
+/// Lower `root` into a [`Module`] and its [`SourceMap`]
+pub fn lower(root: &ast::Root) -> (Module, SourceMap) {
+    let mut lower = Lower::default();
+    let range = root.syntax().text_range();
+    let id = lower.lower_opt(root.expr(), range);
+    lower.module.root = Some(id);
+    (lower.module, lower.source_map)
+}
+
+#[derive(Default)]
+struct Lower {
+    module: Module,
+    source_map: SourceMap,
+}
+
+impl Lower {
+    fn alloc(&mut self, expr: Expr, range: TextRange) -> ExprId {
+        let id = ExprId(self.module.exprs.len() as u32);
+        self.module.exprs.push(expr);
+        self.source_map.ranges.push(range);
+        id
+    }
+
+    /// Lower `expr`, or make a `Missing` expression at `range` if there is none
+    fn lower_opt(&mut self, expr: Option<ast::Expr>, range: TextRange) -> ExprId {
+        match expr {
+            Some(expr) => self.lower_expr(expr),
+            None => self.alloc(Expr::Missing, range),
+        }
+    }
+
+    fn lower_expr(&mut self, expr: ast::Expr) -> ExprId {
+        let range = expr.syntax().text_range();
+        let lowered = match expr {
+            ast::Expr::Paren(paren) => return self.lower_opt(paren.expr(), range),
+            ast::Expr::Root(root) => return self.lower_opt(root.expr(), range),
+            ast::Expr::Error(_) => Expr::Missing,
+            ast::Expr::Literal(literal) => match literal.kind() {
+                LiteralKind::Integer(int) => int
+                    .value()
+                    .map_or(Expr::Missing, |value| Expr::Literal(Literal::Integer(value))),
+                LiteralKind::Float(float) => float
+                    .value()
+                    .map_or(Expr::Missing, |value| Expr::Literal(Literal::Float(value))),
+                LiteralKind::Uri(uri) => Expr::String(vec![StrPart::Literal(uri.to_string())]),
+            },
+            ast::Expr::Str(s) => Expr::String(self.lower_str(&s)),
+            ast::Expr::Path(path) => return self.lower_path(path),
+            ast::Expr::Ident(ident) => Expr::Reference(ident_name(&ident)),
+            ast::Expr::Apply(apply) => Expr::Apply {
+                lambda: self.lower_opt(apply.lambda(), range),
+                argument: self.lower_opt(apply.argument(), range),
+            },
+            ast::Expr::Lambda(lambda) => {
+                let param = match lambda.param() {
+                    Some(ast::Param::IdentParam(param)) => {
+                        Param::Ident(param.ident().map(|i| ident_name(&i)).unwrap_or_default())
+                    }
+                    Some(ast::Param::Pattern(pattern)) => self.lower_pattern(&pattern),
+                    None => Param::Ident(String::new()),
+                };
+                Expr::Lambda { param, body: self.lower_opt(lambda.body(), range) }
+            }
+            ast::Expr::Assert(assert) => Expr::Assert {
+                condition: self.lower_opt(assert.condition(), range),
+                body: self.lower_opt(assert.body(), range),
+            },
+            ast::Expr::IfElse(if_else) => Expr::IfElse {
+                condition: self.lower_opt(if_else.condition(), range),
+                body: self.lower_opt(if_else.body(), range),
+                else_body: self.lower_opt(if_else.else_body(), range),
+            },
+            ast::Expr::Select(select) => Expr::Select {
+                set: self.lower_opt(select.expr(), range),
+                attrpath: self.lower_attrpath(select.attrpath()),
+                default: select.default_expr().map(|default| self.lower_expr(default)),
+            },
+            ast::Expr::HasAttr(has_attr) => Expr::HasAttr {
+                set: self.lower_opt(has_attr.expr(), range),
+                attrpath: self.lower_attrpath(has_attr.attrpath()),
+            },
+            ast::Expr::AttrSet(set) => {
+                let id = self.alloc(
+                    Expr::AttrSet { rec: set.rec_token().is_some(), bindings: vec![] },
+                    range,
+                );
+                self.lower_entries(id, &set);
+                return id;
+            }
+            ast::Expr::LegacyLet(legacy_let) => {
+                let set = self.alloc(Expr::AttrSet { rec: true, bindings: vec![] }, range);
+                self.lower_entries(set, &legacy_let);
+                let attrpath = vec![AttrName::Static("body".to_string())];
+                Expr::Select { set, attrpath, default: None }
+            }
+            ast::Expr::LetIn(let_in) => {
+                // The body is filled in once it's lowered
+                let id = self.alloc(Expr::LetIn { bindings: vec![], body: ExprId(0) }, range);
+                self.lower_entries(id, &let_in);
+                let lowered = self.lower_opt(let_in.body(), range);
+                if let Expr::LetIn { body, .. } = &mut self.module.exprs[id.index()] {
+                    *body = lowered;
+                }
+                return id;
+            }
+            ast::Expr::With(with) => Expr::With {
+                namespace: self.lower_opt(with.namespace(), range),
+                body: self.lower_opt(with.body(), range),
+            },
+            ast::Expr::List(list) => {
+                Expr::List(list.items().map(|item| self.lower_expr(item)).collect())
+            }
+            ast::Expr::BinOp(op) => match op.operator() {
+                Some(operator) => Expr::BinOp {
+                    lhs: self.lower_opt(op.lhs(), range),
+                    operator,
+                    rhs: self.lower_opt(op.rhs(), range),
+                },
+                None => Expr::Missing,
+            },
+            ast::Expr::UnaryOp(op) => match op.operator() {
+                Some(operator) => {
+                    Expr::UnaryOp { operator, expr: self.lower_opt(op.expr(), range) }
+                }
+                None => Expr::Missing,
+            },
+        };
+        self.alloc(lowered, range)
+    }
+
+    fn lower_str(&mut self, s: &ast::Str) -> Vec<StrPart> {
+        s.normalized_parts()
+            .into_iter()
+            .map(|part| match part {
+                InterpolPart::Literal(literal) => StrPart::Literal(literal),
+                InterpolPart::Interpolation(interpol) => {
+                    let range = interpol.syntax().text_range();
+                    StrPart::Interpolation(self.lower_opt(interpol.expr(), range))
+                }
+            })
+            .collect()
+    }
+
+    fn lower_path(&mut self, path: ast::Path) -> ExprId {
+        let range = path.syntax().text_range();
+        let Some(kind) = path.kind() else { return self.alloc(Expr::Missing, range) };
+        if kind == PathKind::Search {
+            let name = path
+                .search_path_name()
+                .map(|name| match path.search_path_subpath() {
+                    Some(subpath) => format!("{name}/{subpath}"),
+                    None => name,
+                })
+                .unwrap_or_default();
+            let find_file = self.alloc(Expr::Reference("__findFile".to_string()), range);
+            let nix_path = self.alloc(Expr::Reference("__nixPath".to_string()), range);
+            let lambda = self.alloc(Expr::Apply { lambda: find_file, argument: nix_path }, range);
+            let argument = self.alloc(Expr::String(vec![StrPart::Literal(name)]), range);
+            return self.alloc(Expr::Apply { lambda, argument }, range);
+        }
+        let parts = path
+            .parts()
+            .map(|part| match part {
+                InterpolPart::Literal(content) => StrPart::Literal(content.to_string()),
+                InterpolPart::Interpolation(interpol) => {
+                    let range = interpol.syntax().text_range();
+                    StrPart::Interpolation(self.lower_opt(interpol.expr(), range))
+                }
+            })
+            .collect();
+        self.alloc(Expr::Path(kind, parts), range)
+    }
+
+    fn lower_pattern(&mut self, pattern: &ast::Pattern) -> Param {
+        let entries = pattern
+            .pat_entries()
+            .map(|entry| PatEntry {
+                name: entry.ident().map(|i| ident_name(&i)).unwrap_or_default(),
+                default: entry.default().map(|default| self.lower_expr(default)),
+            })
+            .collect();
+        Param::Pattern {
+            entries,
+            ellipsis: pattern.ellipsis_token().is_some(),
+            bind: pattern.pat_bind().and_then(|bind| bind.ident()).map(|i| ident_name(&i)),
+        }
+    }
+
+    fn lower_attrpath(&mut self, attrpath: Option<ast::Attrpath>) -> Vec<AttrName> {
+        attrpath
+            .map(|attrpath| attrpath.attrs().map(|attr| self.lower_attr(attr)).collect())
+            .unwrap_or_default()
+    }
+
+    fn lower_attr(&mut self, attr: ast::Attr) -> AttrName {
+        if let Some(name) = attr.static_name() {
+            return AttrName::Static(name);
+        }
+        let range = attr.syntax().text_range();
+        AttrName::Dynamic(match attr {
+            ast::Attr::Dynamic(dynamic) => self.lower_opt(dynamic.expr(), range),
+            ast::Attr::Str(s) => {
+                let parts = self.lower_str(&s);
+                self.alloc(Expr::String(parts), range)
+            }
+            ast::Attr::Ident(_) => self.alloc(Expr::Missing, range),
+        })
+    }
+
+    /// Lower the entries of `node` into the bindings of the set or `let` `id`
+    fn lower_entries(&mut self, id: ExprId, node: &impl HasEntry) {
+        for entry in node.entries() {
+            match entry {
+                ast::Entry::Inherit(inherit) => self.lower_inherit(id, &inherit),
+                ast::Entry::AttrpathValue(entry) => {
+                    let range = entry.syntax().text_range();
+                    let path = self.lower_attrpath(entry.attrpath());
+                    self.lower_attrpath_value(id, path, entry.value(), range);
+                }
+            }
+        }
+    }
+
+    fn lower_inherit(&mut self, id: ExprId, inherit: &ast::Inherit) {
+        let from = inherit.from().map(|from| {
+            let range = from.syntax().text_range();
+            self.lower_opt(from.expr(), range)
+        });
+        for attr in inherit.attrs() {
+            let range = attr.syntax().text_range();
+            let name = self.lower_attr(attr);
+            let value = match (from, &name) {
+                (Some(set), name) => self.alloc(
+                    Expr::Select { set, attrpath: vec![name.clone()], default: None },
+                    range,
+                ),
+                (None, AttrName::Static(name)) => self.alloc(Expr::Reference(name.clone()), range),
+                (None, AttrName::Dynamic(_)) => self.alloc(Expr::Missing, range),
+            };
+            let kind = match from {
+                Some(_) => BindingKind::InheritedFrom,
+                None => BindingKind::Inherited,
+            };
+            self.bindings(id).push(Binding { name, value, kind });
+        }
+    }
+
+    fn lower_attrpath_value(
+        &mut self,
+        id: ExprId,
+        mut path: Vec<AttrName>,
+        value: Option<ast::Expr>,
+        range: TextRange,
+    ) {
+        if path.is_empty() {
+            return;
+        }
+        let name = path.remove(0);
+        let existing = self.mergeable(id, &name);
+        if path.is_empty() {
+            match (existing, value.clone().and_then(without_parens)) {
+                // `a.b = 1; a = { c = 2; };` merges the sets, like Nix does
+                (Some(set), Some(ast::Expr::AttrSet(set_value))) => {
+                    self.lower_entries(set, &set_value);
+                }
+                _ => {
+                    let value = self.lower_opt(value, range);
+                    self.bindings(id).push(Binding { name, value, kind: BindingKind::Plain });
+                }
+            }
+        } else {
+            let set = match existing {
+                Some(set) => set,
+                None => {
+                    let set = self.alloc(Expr::AttrSet { rec: false, bindings: vec![] }, range);
+                    self.bindings(id).push(Binding { name, value: set, kind: BindingKind::Plain });
+                    set
+                }
+            };
+            self.lower_attrpath_value(set, path, value, range);
+        }
+    }
+
+    /// An existing binding of `name` in `id` to a set that other bindings can
+    /// be merged into
+    fn mergeable(&mut self, id: ExprId, name: &AttrName) -> Option<ExprId> {
+        let AttrName::Static(_) = name else { return None };
+        let binding = self.bindings(id).iter().find(|binding| binding.name == *name)?;
+        let value = binding.value;
+        match self.module[value] {
+            Expr::AttrSet { .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    fn bindings(&mut self, id: ExprId) -> &mut Vec<Binding> {
+        match &mut self.module.exprs[id.index()] {
+            Expr::AttrSet { bindings, .. } | Expr::LetIn { bindings, .. } => bindings,
+            _ => unreachable!("only sets and lets have bindings"),
+        }
+    }
+}
+
+/// The expression inside any parentheses around `expr`
+fn without_parens(mut expr: ast::Expr) -> Option<ast::Expr> {
+    while let ast::Expr::Paren(paren) = expr {
+        expr = paren.expr()?;
+    }
+    Some(expr)
+}
+
+/// The name of an identifier, which can be `or`
+fn ident_name(ident: &ast::Ident) -> String {
+    ident
+        .syntax()
+        .children_with_tokens()
+        .filter_map(|element| element.into_token())
+        .find(|token| matches!(token.kind(), TOKEN_IDENT | TOKEN_OR))
+        .map(|token| token.text().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rowan::{ast::AstNode, TextRange, TextSize};
+
+    use super::*;
+
+    fn lower_str(code: &str) -> (Module, SourceMap) {
+        let root = crate::Root::parse(code).ok().unwrap();
+        lower(&root)
+    }
+
+    /// Lower `code` and print it like `nix-instantiate --parse`
+    fn show(code: &str) -> String {
+        let (module, _) = lower_str(code);
+        print::show(&module, module.root(), Path::new("/base/dir"))
+    }
+
+    #[test]
+    fn desugar() {
+        assert_eq!(show("((1))"), "1");
+        assert_eq!(
+            show("{ a.b.c = 1; a.b.d = 2; a = { e = 3; }; ${x}.y = 4; }"),
+            r#"{ a = { b = { c = 1; d = 2; }; e = 3; }; "${x}" = { y = 4; }; }"#
+        );
+        assert_eq!(
+            show("rec { inherit a; inherit (b) c d; }"),
+            "rec { inherit a; inherit (b) c d; }"
+        );
+        assert_eq!(show("let { x = 1; body = x; }"), "(rec { body = x; x = 1; }).body");
+        assert_eq!(show("{ a = rec { x = 1; }; a.y = 2; }"), "{ a = rec { x = 1; y = 2; }; }");
+        assert_eq!(show("{ a.b = 1; a = ({ c = 2; }); }"), "{ a = { b = 1; c = 2; }; }");
+        assert_eq!(show("<nixpkgs/lib>"), r#"(__findFile __nixPath "nixpkgs/lib")"#);
+        assert_eq!(show("f or"), "(f or)");
+        assert_eq!(show("a.b or c"), "(a).b or (c)");
+        assert_eq!(show("https://nixos.org"), r#""https://nixos.org""#);
+        assert_eq!(show("{ a, b ? 1, ... }@args: a"), "({ a, b ? 1, ... } @ args: a)");
+        assert_eq!(show("let a = 1; in a + -a"), "(let a = 1; in (a + (__sub 0 a)))");
+        assert_eq!(show("./a/${b}"), "(/base/dir/a/ + b)");
+        assert_eq!(show("\"a${b}\""), r#"("a" + b)"#);
+    }
+
+    #[test]
+    fn missing() {
+        let root = crate::Root::parse("{ a = ; }").tree();
+        let (module, _) = lower(&root);
+        assert_eq!(print::show(&module, module.root(), Path::new("/")), "{ a = <missing>; }");
+    }
+
+    #[test]
+    fn source_map() {
+        let code = "{ a.b = (f <p>); }";
+        let (module, source_map) = lower_str(code);
+        let range = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+
+        let apply = source_map.expr_for_range(&module, range(9, 14)).unwrap();
+        assert!(matches!(module[apply], Expr::Apply { .. }));
+        let search = source_map.expr_for_range(&module, range(11, 14)).unwrap();
+        let Expr::Apply { argument, .. } = module[apply] else { unreachable!() };
+        assert_eq!(search, argument);
+        assert!(matches!(module[search], Expr::Apply { .. }));
+
+        for (id, _) in module.exprs() {
+            assert!(source_map.range(id).end() <= TextSize::of(code));
+        }
+        assert_eq!(
+            source_map.range(module.root()),
+            crate::Root::parse(code).tree().syntax().text_range()
+        );
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Module>();
+        assert_send_sync::<SourceMap>();
+    }
+}
@@ -0,0 +1,492 @@
+//! Print expressions the way `nix-instantiate --parse` shows them, to compare
+//! the parser with Nix on the same code.
+
+use std::path::Path as FsPath;
+
+use super::{AttrName, Binding, BindingKind, Expr, ExprId, Literal, Module, Param, StrPart};
+use crate::ast::{resolve_prefix, BinOpKind, UnaryOpKind};
+
+/// Show the expression `id` like `nix-instantiate --parse` does, with every
+/// operation in parentheses, the arguments of calls collected and attribute
+/// names sorted. `base_dir` is the directory relative paths are resolved
+/// against.
+///
+/// Nix turns some operators into calls of builtins, like `(__sub a b)` for
+/// `a - b` and `(! (__lessThan b a))` for `a <= b`, and so does this. Home
+/// paths are shown as written, since Nix replaces `~` with the home
+/// directory of whoever runs it. Expressions missing because of errors are
+/// shown as `<missing>`.
+pub fn show(module: &Module, id: ExprId, base_dir: &FsPath) -> String {
+    let mut printer = Printer { module, base_dir, out: String::new() };
+    printer.node(&printer.desugar(id));
+    printer.out
+}
+
+/// An expression as Nix sees it, where operators may be calls
+enum Node {
+    Expr(ExprId),
+    Call(Box<Node>, Vec<Node>),
+    Builtin(&'static str),
+    Zero,
+    Not(Box<Node>),
+}
+
+struct Printer<'a> {
+    module: &'a Module,
+    base_dir: &'a FsPath,
+    out: String,
+}
+
+impl Printer<'_> {
+    fn desugar(&self, id: ExprId) -> Node {
+        let builtin = |name, args| Node::Call(Box::new(Node::Builtin(name)), args);
+        let less_than =
+            |lhs, rhs| builtin("__lessThan", vec![self.desugar(lhs), self.desugar(rhs)]);
+        match self.module[id] {
+            Expr::Apply { lambda, argument } => call(self.desugar(lambda), self.desugar(argument)),
+            Expr::BinOp { lhs, operator, rhs } => match operator {
+                BinOpKind::Sub => builtin("__sub", vec![self.desugar(lhs), self.desugar(rhs)]),
+                BinOpKind::Mul => builtin("__mul", vec![self.desugar(lhs), self.desugar(rhs)]),
+                BinOpKind::Div => builtin("__div", vec![self.desugar(lhs), self.desugar(rhs)]),
+                BinOpKind::Less => less_than(lhs, rhs),
+                BinOpKind::More => less_than(rhs, lhs),
+                BinOpKind::LessOrEq => Node::Not(Box::new(less_than(rhs, lhs))),
+                BinOpKind::MoreOrEq => Node::Not(Box::new(less_than(lhs, rhs))),
+                BinOpKind::PipeRight => call(self.desugar(rhs), self.desugar(lhs)),
+                BinOpKind::PipeLeft => call(self.desugar(lhs), self.desugar(rhs)),
+                _ => Node::Expr(id),
+            },
+            Expr::UnaryOp { operator: UnaryOpKind::Negate, expr } => {
+                builtin("__sub", vec![Node::Zero, self.desugar(expr)])
+            }
+            _ => Node::Expr(id),
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Expr(id) => self.expr(*id),
+            Node::Call(function, args) => {
+                self.out.push('(');
+                self.node(function);
+                for arg in args {
+                    self.out.push(' ');
+                    self.node(arg);
+                }
+                self.out.push(')');
+            }
+            Node::Builtin(name) => self.out.push_str(name),
+            Node::Zero => self.out.push('0'),
+            Node::Not(node) => {
+                self.out.push_str("(! ");
+                self.node(node);
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn show(&mut self, id: ExprId) {
+        let node = self.desugar(id);
+        self.node(&node);
+    }
+
+    fn expr(&mut self, id: ExprId) {
+        match &self.module[id] {
+            Expr::Missing => self.out.push_str("<missing>"),
+            Expr::Literal(Literal::Integer(i)) => self.out.push_str(&i.to_string()),
+            Expr::Literal(Literal::Float(f)) => self.out.push_str(&format_float(*f)),
+            Expr::String(parts) => {
+                let parts: Vec<_> = parts
+                    .iter()
+                    .filter(|p| !matches!(p, StrPart::Literal(s) if s.is_empty()))
+                    .collect();
+                match parts[..] {
+                    [] => self.out.push_str("\"\""),
+                    [StrPart::Literal(s)] => self.string(s),
+                    _ => self.concat(parts),
+                }
+            }
+            Expr::Path(kind, parts) => {
+                let mut parts = parts.iter();
+                let Some(StrPart::Literal(prefix)) = parts.next() else { unreachable!() };
+                let prefix = resolve_prefix(*kind, prefix, self.base_dir)
+                    .expect("search paths are desugared");
+                if parts.len() == 0 {
+                    self.out.push_str(&prefix);
+                } else {
+                    self.out.push('(');
+                    self.out.push_str(&prefix);
+                    for part in parts {
+                        self.out.push_str(" + ");
+                        self.part(part);
+                    }
+                    self.out.push(')');
+                }
+            }
+            Expr::Reference(name) => self.out.push_str(name),
+            Expr::Lambda { param, body } => {
+                self.out.push('(');
+                match param {
+                    Param::Ident(name) => self.out.push_str(name),
+                    Param::Pattern { entries, ellipsis, bind } => {
+                        let mut entries: Vec<_> = entries.iter().collect();
+                        entries.sort_by(|a, b| a.name.cmp(&b.name));
+                        self.out.push_str("{ ");
+                        for (i, entry) in entries.iter().enumerate() {
+                            if i > 0 {
+                                self.out.push_str(", ");
+                            }
+                            self.out.push_str(&entry.name);
+                            if let Some(default) = entry.default {
+                                self.out.push_str(" ? ");
+                                self.show(default);
+                            }
+                        }
+                        if *ellipsis {
+                            if !entries.is_empty() {
+                                self.out.push_str(", ");
+                            }
+                            self.out.push_str("...");
+                        }
+                        self.out.push_str(" }");
+                        if let Some(bind) = bind {
+                            self.out.push_str(" @ ");
+                            self.out.push_str(bind);
+                        }
+                    }
+                }
+                self.out.push_str(": ");
+                self.show(*body);
+                self.out.push(')');
+            }
+            Expr::Assert { condition, body } => {
+                self.out.push_str("assert ");
+                self.show(*condition);
+                self.out.push_str("; ");
+                self.show(*body);
+            }
+            Expr::IfElse { condition, body, else_body } => {
+                self.out.push_str("(if ");
+                self.show(*condition);
+                self.out.push_str(" then ");
+                self.show(*body);
+                self.out.push_str(" else ");
+                self.show(*else_body);
+                self.out.push(')');
+            }
+            Expr::Select { set, attrpath, default } => {
+                self.out.push('(');
+                self.show(*set);
+                self.out.push_str(").");
+                self.attrpath(attrpath);
+                if let Some(default) = default {
+                    self.out.push_str(" or (");
+                    self.show(*default);
+                    self.out.push(')');
+                }
+            }
+            Expr::HasAttr { set, attrpath } => {
+                self.out.push_str("((");
+                self.show(*set);
+                self.out.push_str(") ? ");
+                self.attrpath(attrpath);
+                self.out.push(')');
+            }
+            Expr::AttrSet { rec, bindings } => {
+                if *rec {
+                    self.out.push_str("rec ");
+                }
+                self.out.push_str("{ ");
+                self.bindings(bindings);
+                self.out.push('}');
+            }
+            Expr::LetIn { bindings, body } => {
+                self.out.push_str("(let ");
+                self.bindings(bindings);
+                self.out.push_str("in ");
+                self.show(*body);
+                self.out.push(')');
+            }
+            Expr::With { namespace, body } => {
+                self.out.push_str("(with ");
+                self.show(*namespace);
+                self.out.push_str("; ");
+                self.show(*body);
+                self.out.push(')');
+            }
+            Expr::List(items) => {
+                self.out.push_str("[ ");
+                for &item in items {
+                    self.show(item);
+                    self.out.push(' ');
+                }
+                self.out.push(']');
+            }
+            Expr::BinOp { lhs, operator, rhs } => {
+                let symbol = match operator {
+                    BinOpKind::Concat => "++",
+                    BinOpKind::Update => "//",
+                    BinOpKind::Add => "+",
+                    BinOpKind::And => "&&",
+                    BinOpKind::Equal => "==",
+                    BinOpKind::Implication => "->",
+                    BinOpKind::NotEqual => "!=",
+                    BinOpKind::Or => "||",
+                    _ => unreachable!("{:?} is a call", operator),
+                };
+                self.out.push('(');
+                self.show(*lhs);
+                self.out.push_str(&format!(" {symbol} "));
+                self.show(*rhs);
+                self.out.push(')');
+            }
+            Expr::UnaryOp { operator, expr } => {
+                debug_assert_eq!(*operator, UnaryOpKind::Invert);
+                self.out.push_str("(! ");
+                self.show(*expr);
+                self.out.push(')');
+            }
+            Expr::Apply { .. } => unreachable!("calls are desugared"),
+        }
+    }
+
+    fn concat(&mut self, parts: Vec<&StrPart>) {
+        self.out.push('(');
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(" + ");
+            }
+            self.part(part);
+        }
+        self.out.push(')');
+    }
+
+    fn part(&mut self, part: &StrPart) {
+        match part {
+            StrPart::Literal(s) => self.string(s),
+            StrPart::Interpolation(id) => self.show(*id),
+        }
+    }
+
+    /// Quote a string like Nix's `printLiteralString`
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                '$' if chars.peek() == Some(&'{') => self.out.push_str("\\$"),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn attr_name(&mut self, name: &AttrName) {
+        match name {
+            AttrName::Static(name) => self.out.push_str(name),
+            AttrName::Dynamic(id) => {
+                self.out.push_str("\"${");
+                self.show(*id);
+                self.out.push_str("}\"");
+            }
+        }
+    }
+
+    fn attrpath(&mut self, attrpath: &[AttrName]) {
+        for (i, name) in attrpath.iter().enumerate() {
+            if i > 0 {
+                self.out.push('.');
+            }
+            self.attr_name(name);
+        }
+    }
+
+    /// Nix shows the inherited names first, then those inherited from each
+    /// source, then the other names in order and the dynamic ones last
+    fn bindings(&mut self, bindings: &[Binding]) {
+        let mut sorted: Vec<(&str, &Binding)> = bindings
+            .iter()
+            .filter_map(|b| match &b.name {
+                AttrName::Static(name) => Some((name.as_str(), b)),
+                AttrName::Dynamic(_) => None,
+            })
+            .collect();
+        sorted.sort_by_key(|(name, _)| *name);
+
+        let inherited: Vec<_> =
+            sorted.iter().filter(|(_, b)| b.kind == BindingKind::Inherited).collect();
+        if !inherited.is_empty() {
+            self.out.push_str("inherit");
+            for (name, _) in inherited {
+                self.out.push(' ');
+                self.out.push_str(name);
+            }
+            self.out.push_str("; ");
+        }
+
+        let mut sources = Vec::new();
+        for binding in bindings.iter().filter(|b| b.kind == BindingKind::InheritedFrom) {
+            if let Expr::Select { set, .. } = self.module[binding.value] {
+                if !sources.contains(&set) {
+                    sources.push(set);
+                }
+            }
+        }
+        for source in sources {
+            self.out.push_str("inherit (");
+            self.show(source);
+            self.out.push(')');
+            for (name, binding) in &sorted {
+                match self.module[binding.value] {
+                    Expr::Select { set, .. }
+                        if binding.kind == BindingKind::InheritedFrom && set == source =>
+                    {
+                        self.out.push(' ');
+                        self.out.push_str(name);
+                    }
+                    _ => (),
+                }
+            }
+            self.out.push_str("; ");
+        }
+
+        for (name, binding) in &sorted {
+            if binding.kind == BindingKind::Plain {
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.show(binding.value);
+                self.out.push_str("; ");
+            }
+        }
+
+        for binding in bindings {
+            if let AttrName::Dynamic(_) = binding.name {
+                self.attr_name(&binding.name);
+                self.out.push_str(" = ");
+                self.show(binding.value);
+                self.out.push_str("; ");
+            }
+        }
+    }
+}
+
+/// Apply `function` to `arg`, adding to the arguments if it's a call already
+fn call(function: Node, arg: Node) -> Node {
+    match function {
+        Node::Call(function, mut args) => {
+            args.push(arg);
+            Node::Call(function, args)
+        }
+        function => Node::Call(Box::new(function), vec![arg]),
+    }
+}
+
+/// Format a float like C's `%g`, which is how Nix prints them: six
+/// significant digits, without trailing zeros, and in exponent notation if
+/// the exponent is below -4 or above 5
+fn format_float(f: f64) -> String {
+    if f == 0.0 || !f.is_finite() {
+        return f.to_string();
+    }
+    let scientific = format!("{:.5e}", f);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if (-4..6).contains(&exponent) {
+        let fixed = format!("{:.*}", (5 - exponent) as usize, f);
+        trim_zeros(&fixed).to_string()
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exponent.abs())
+    }
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{format_float, show};
+    use crate::hir::lower;
+
+    fn parse(code: &str) -> String {
+        let root = crate::Root::parse(code).ok().unwrap();
+        let (module, _) = lower(&root);
+        show(&module, module.root(), Path::new("/base/dir"))
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(parse("1 + 2 * 3 - 4 / 2"), "(__sub (1 + (__mul 2 3)) (__div 4 2))");
+        assert_eq!(parse("a -> b -> c"), "(a -> (b -> c))");
+        assert_eq!(parse("a || b && c"), "(a || (b && c))");
+        assert_eq!(parse("a ++ b ++ c"), "(a ++ (b ++ c))");
+        assert_eq!(parse("a // b // c"), "(a // (b // c))");
+        assert_eq!(parse("a == b"), "(a == b)");
+        assert_eq!(parse("a < b"), "(__lessThan a b)");
+        assert_eq!(parse("a > b"), "(__lessThan b a)");
+        assert_eq!(parse("a <= b"), "(! (__lessThan b a))");
+        assert_eq!(parse("a >= b"), "(! (__lessThan a b))");
+        assert_eq!(parse("!a.b or c"), "(! (a).b or (c))");
+        assert_eq!(parse("-a ? b"), "(((__sub 0 a)) ? b)");
+        assert_eq!(parse("f a b - 1"), "(__sub (f a b) 1)");
+        assert_eq!(parse("a |> f b |> g"), "(g (f b a))");
+        assert_eq!(parse("f <| g <| a"), "(f (g a))");
+        assert_eq!(parse("<nixpkgs/lib>"), "(__findFile __nixPath \"nixpkgs/lib\")");
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse("\"a\\\"${b}\\n\\${c}\""), "(\"a\\\"\" + b + \"\\n\\${c}\")");
+        assert_eq!(parse("\"${a}\""), "(a)");
+        assert_eq!(parse("''\n  a\n  ''"), "\"a\\n\"");
+        assert_eq!(parse("\"\""), "\"\"");
+        assert_eq!(parse("./a/../b"), "/base/dir/b");
+        assert_eq!(parse("../a/${b}/c"), "(/base/a/ + b + \"/c\")");
+        assert_eq!(parse("[ 1 2.5 [ ] ]"), "[ 1 2.5 [ ] ]");
+        assert_eq!(parse("let { a = 1; body = a; }"), "(rec { a = 1; body = a; }).body");
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(
+            parse("rec { z = 1; inherit b a; inherit (x) d c; inherit (y) e; ${k} = 2; \"a${b}\" = 4; m.n.o = 3; }"),
+            "rec { inherit a b; inherit (x) c d; inherit (y) e; m = { n = { o = 3; }; }; z = 1; \"${k}\" = 2; \"${(\"a\" + b)}\" = 4; }"
+        );
+        assert_eq!(parse("let a = 1; in a"), "(let a = 1; in a)");
+        assert_eq!(parse("{ }"), "{ }");
+        assert_eq!(parse("with a; assert b; c"), "(with a; assert b; c)");
+        assert_eq!(parse("if a then b else c"), "(if a then b else c)");
+    }
+
+    #[test]
+    fn lambdas() {
+        assert_eq!(parse("x: y: x"), "(x: (y: x))");
+        assert_eq!(parse("{ b, a ? 1, ... } @ args: a"), "({ a ? 1, b, ... } @ args: a)");
+        assert_eq!(parse("{ }: 1"), "({  }: 1)");
+        assert_eq!(parse("{ ... }: 1"), "({ ... }: 1)");
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(format_float(2.5), "2.5");
+        assert_eq!(format_float(1.0), "1");
+        assert_eq!(format_float(100000.0), "100000");
+        assert_eq!(format_float(1000000.0), "1e+06");
+        assert_eq!(format_float(1e20), "1e+20");
+        assert_eq!(format_float(0.0001), "0.0001");
+        assert_eq!(format_float(0.00001234), "1.234e-05");
+        assert_eq!(format_float(1234.5678), "1234.57");
+    }
+}
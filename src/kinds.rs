@@ -1,6 +1,9 @@
+use std::fmt;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u16)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SyntaxKind {
     // Internals
     TOKEN_COMMENT,
@@ -100,6 +103,10 @@ pub enum SyntaxKind {
     // Attrpath existence check: foo ? bar.${baz}."bux"
     NODE_HAS_ATTR,
 
+    // Kinds added later go last, so the numbers of the others stay the same
+    TOKEN_PIPE_LEFT,
+    TOKEN_PIPE_RIGHT,
+
     #[doc(hidden)]
     __LAST,
 }
@@ -134,3 +141,106 @@ impl SyntaxKind {
         matches!(self, TOKEN_COMMENT | TOKEN_ERROR | TOKEN_WHITESPACE)
     }
 }
+
+/// A human-readable name, such as "`{`" or "identifier", for use in messages
+impl fmt::Display for SyntaxKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TOKEN_COMMENT => "comment",
+            TOKEN_ERROR => "invalid token",
+            TOKEN_WHITESPACE => "whitespace",
+
+            TOKEN_ASSERT => "`assert`",
+            TOKEN_ELSE => "`else`",
+            TOKEN_IF => "`if`",
+            TOKEN_IN => "`in`",
+            TOKEN_INHERIT => "`inherit`",
+            TOKEN_LET => "`let`",
+            TOKEN_OR => "`or`",
+            TOKEN_REC => "`rec`",
+            TOKEN_THEN => "`then`",
+            TOKEN_WITH => "`with`",
+
+            TOKEN_L_BRACE => "`{`",
+            TOKEN_R_BRACE => "`}`",
+            TOKEN_L_BRACK => "`[`",
+            TOKEN_R_BRACK => "`]`",
+            TOKEN_ASSIGN => "`=`",
+            TOKEN_AT => "`@`",
+            TOKEN_COLON => "`:`",
+            TOKEN_COMMA => "`,`",
+            TOKEN_DOT => "`.`",
+            TOKEN_ELLIPSIS => "`...`",
+            TOKEN_QUESTION => "`?`",
+            TOKEN_SEMICOLON => "`;`",
+
+            TOKEN_L_PAREN => "`(`",
+            TOKEN_R_PAREN => "`)`",
+            TOKEN_CONCAT => "`++`",
+            TOKEN_INVERT => "`!`",
+            TOKEN_UPDATE => "`//`",
+
+            TOKEN_ADD => "`+`",
+            TOKEN_SUB => "`-`",
+            TOKEN_MUL => "`*`",
+            TOKEN_DIV => "`/`",
+
+            TOKEN_AND_AND => "`&&`",
+            TOKEN_EQUAL => "`==`",
+            TOKEN_IMPLICATION => "`->`",
+            TOKEN_LESS => "`<`",
+            TOKEN_LESS_OR_EQ => "`<=`",
+            TOKEN_MORE => "`>`",
+            TOKEN_MORE_OR_EQ => "`>=`",
+            TOKEN_NOT_EQUAL => "`!=`",
+            TOKEN_OR_OR => "`||`",
+            TOKEN_PIPE_LEFT => "`<|`",
+            TOKEN_PIPE_RIGHT => "`|>`",
+
+            TOKEN_FLOAT => "float",
+            TOKEN_IDENT => "identifier",
+            TOKEN_INTEGER => "integer",
+            TOKEN_INTERPOL_END => "end of interpolation",
+            TOKEN_INTERPOL_START => "`${`",
+            TOKEN_PATH => "path",
+            TOKEN_URI => "URI",
+            TOKEN_STRING_CONTENT => "string content",
+            TOKEN_STRING_END => "end of string",
+            TOKEN_STRING_START => "string",
+
+            NODE_APPLY => "function application",
+            NODE_ASSERT => "assertion",
+            NODE_ATTRPATH => "attribute path",
+            NODE_DYNAMIC => "dynamic attribute",
+            NODE_ERROR => "error",
+            NODE_IDENT => "identifier",
+            NODE_IF_ELSE => "if expression",
+            NODE_SELECT => "attribute selection",
+            NODE_INHERIT => "inherit",
+            NODE_INHERIT_FROM => "inherit source",
+            NODE_STRING => "string",
+            NODE_INTERPOL => "interpolation",
+            NODE_LAMBDA => "function",
+            NODE_IDENT_PARAM => "function argument",
+            NODE_LEGACY_LET => "legacy let",
+            NODE_LET_IN => "let expression",
+            NODE_LIST => "list",
+            NODE_BIN_OP => "binary operation",
+            NODE_PAREN => "parenthesized expression",
+            NODE_PATTERN => "pattern",
+            NODE_PAT_BIND => "pattern binding",
+            NODE_PAT_ENTRY => "pattern entry",
+            NODE_ROOT => "root",
+            NODE_ATTR_SET => "attribute set",
+            NODE_ATTRPATH_VALUE => "binding",
+            NODE_UNARY_OP => "unary operation",
+            NODE_LITERAL => "literal",
+            NODE_WITH => "with expression",
+            NODE_PATH => "path",
+            NODE_HAS_ATTR => "attribute check",
+
+            __LAST => "unknown",
+        };
+        f.write_str(name)
+    }
+}
@@ -1,24 +1,30 @@
 #[macro_use]
 mod macros;
 pub mod ast;
+pub mod diagnostic;
+pub mod hir;
 mod kinds;
 pub mod parser;
+mod reparse;
+#[cfg(feature = "serde")]
+pub mod serialize;
 #[cfg(test)]
 mod tests;
 mod token_set;
 pub mod tokenizer;
+mod validation;
 
 use std::marker::PhantomData;
 
 pub use self::{kinds::SyntaxKind, tokenizer::tokenize};
 
 use ast::AstNode;
-use parser::ParseError;
+use parser::{EntryPoint, ParseError, ParseOptions};
 use rowan::GreenNode;
 pub use rowan::{NodeOrToken, TextRange, TextSize, TokenAtOffset, WalkEvent};
 pub(crate) use token_set::TokenSet;
 
-use self::tokenizer::Tokenizer;
+use self::tokenizer::{LexError, Tokenizer};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum NixLanguage {}
@@ -45,8 +51,77 @@ pub use ast::Root;
 
 impl Root {
     pub fn parse(s: &str) -> Parse<Root> {
-        let (green, errors) = parser::parse(Tokenizer::new(s));
-        Parse { green, errors, _ty: PhantomData }
+        Self::parse_with(s, ParseOptions::default())
+    }
+
+    /// Parse a document, accepting the dialect of Nix selected by `options`
+    pub fn parse_with(s: &str, options: ParseOptions) -> Parse<Root> {
+        Parse::from_tokens(Tokenizer::new(s), EntryPoint::Root, options)
+    }
+
+    /// Parse a document that might not be valid UTF-8. Every invalid byte
+    /// becomes a `\0` in the tree, so ranges still match the input, and is
+    /// reported as `LexError::InvalidUtf8`.
+    pub fn parse_bytes(bytes: &[u8]) -> Parse<Root> {
+        Self::parse_bytes_with(bytes, ParseOptions::default())
+    }
+
+    /// Like `parse_bytes`, accepting the dialect of Nix selected by `options`
+    pub fn parse_bytes_with(bytes: &[u8], options: ParseOptions) -> Parse<Root> {
+        let (s, invalid) = tokenizer::sanitize(bytes);
+        let tokenizer = Tokenizer::new(&s).with_invalid(invalid);
+        Parse::from_tokens(tokenizer, EntryPoint::Root, options)
+    }
+}
+
+impl ast::Expr {
+    /// Parse a single expression. Unlike with `Root::parse`, `Parse::tree`
+    /// is the expression itself, and the root of `Parse::syntax` only holds
+    /// it with the trivia and errors around it.
+    pub fn parse(s: &str) -> Parse<ast::Expr> {
+        Self::parse_with(s, ParseOptions::default())
+    }
+
+    /// Parse a single expression, accepting the dialect of Nix selected by `options`
+    pub fn parse_with(s: &str, options: ParseOptions) -> Parse<ast::Expr> {
+        Parse::from_tokens(Tokenizer::new(s), EntryPoint::Expr, options)
+    }
+}
+
+impl ast::Attrpath {
+    /// Parse an attribute path, such as `services.nginx.enable`
+    pub fn parse(s: &str) -> Parse<ast::Attrpath> {
+        Self::parse_with(s, ParseOptions::default())
+    }
+
+    /// Parse an attribute path, accepting the dialect of Nix selected by `options`
+    pub fn parse_with(s: &str, options: ParseOptions) -> Parse<ast::Attrpath> {
+        Parse::from_tokens(Tokenizer::new(s), EntryPoint::Attrpath, options)
+    }
+}
+
+impl ast::Pattern {
+    /// Parse a lambda pattern, such as `{ a, b ? 1, ... }@args`
+    pub fn parse(s: &str) -> Parse<ast::Pattern> {
+        Self::parse_with(s, ParseOptions::default())
+    }
+
+    /// Parse a lambda pattern, accepting the dialect of Nix selected by `options`
+    pub fn parse_with(s: &str, options: ParseOptions) -> Parse<ast::Pattern> {
+        Parse::from_tokens(Tokenizer::new(s), EntryPoint::Pattern, options)
+    }
+}
+
+impl ast::AttrSet {
+    /// Parse the bindings of an attribute set without the surrounding braces,
+    /// such as `a = 1; inherit b;`
+    pub fn parse_bindings(s: &str) -> Parse<ast::AttrSet> {
+        Self::parse_bindings_with(s, ParseOptions::default())
+    }
+
+    /// Parse bindings without braces, accepting the dialect of Nix selected by `options`
+    pub fn parse_bindings_with(s: &str, options: ParseOptions) -> Parse<ast::AttrSet> {
+        Parse::from_tokens(Tokenizer::new(s), EntryPoint::Bindings, options)
     }
 }
 
@@ -55,10 +130,49 @@ impl Root {
 pub struct Parse<T> {
     green: GreenNode,
     errors: Vec<ParseError>,
+    options: ParseOptions,
+    entry: EntryPoint,
+    /// The deepest the parser was nested, or more after incremental reparsing
+    depth: u32,
     _ty: PhantomData<fn() -> T>,
 }
 
 impl<T> Parse<T> {
+    fn from_tokens(mut tokenizer: Tokenizer, entry: EntryPoint, options: ParseOptions) -> Self {
+        let (green, errors, depth) = parser::parse_entry(&mut tokenizer, entry, options);
+        Parse::new(green, errors, tokenizer.errors(), options, entry, depth)
+    }
+
+    /// Finish a parse by adding the tokenizer's errors and running the checks
+    /// that need the whole tree
+    fn new(
+        green: GreenNode,
+        mut errors: Vec<ParseError>,
+        lex_errors: &[(TextRange, LexError)],
+        options: ParseOptions,
+        entry: EntryPoint,
+        depth: u32,
+    ) -> Self {
+        errors.extend(parser::lex_errors(lex_errors));
+        let validation = validation::validate(&SyntaxNode::new_root(green.clone()));
+        Self::with_validation(green, errors, validation, options, entry, depth)
+    }
+
+    /// Finish a parse whose tree has already been checked
+    fn with_validation(
+        green: GreenNode,
+        mut errors: Vec<ParseError>,
+        validation: Vec<ParseError>,
+        options: ParseOptions,
+        entry: EntryPoint,
+        depth: u32,
+    ) -> Self {
+        // Keep errors in the order they appear in, so they can be merged when reparsing
+        errors.sort_by_key(|err| (err.range().is_none(), err.range().map(|range| range.start())));
+        errors.extend(validation);
+        Parse { green, errors, options, entry, depth, _ty: PhantomData }
+    }
+
     pub fn syntax(&self) -> SyntaxNode {
         SyntaxNode::new_root(self.green.clone())
     }
@@ -66,12 +180,17 @@ impl<T> Parse<T> {
 
 impl<T: AstNode> Parse<T> {
     pub fn tree(&self) -> T {
-        T::cast(self.syntax()).unwrap()
+        let root = self.syntax();
+        match self.entry {
+            EntryPoint::Root => T::cast(root).unwrap(),
+            // Fragments are the first node in the root, after any trivia
+            _ => root.first_child().and_then(T::cast).unwrap(),
+        }
     }
 
     /// Return all errors in the tree, if any
     pub fn errors(&self) -> &[ParseError] {
-        &*self.errors
+        &self.errors
     }
 
     /// Either return the first error in the tree, or if there are none return self
@@ -5,7 +5,7 @@ use std::{collections::VecDeque, fmt};
 use rowan::{Checkpoint, GreenNode, GreenNodeBuilder, Language, TextRange, TextSize};
 
 use crate::{
-    tokenizer::Token,
+    tokenizer::{LexError, Token},
     NixLanguage,
     SyntaxKind::{self, *},
     TokenSet,
@@ -32,6 +32,16 @@ pub enum ParseError {
     /// RecursionLimitExceeded is used when we're unable to parse further due to likely being close to
     /// a stack overflow.
     RecursionLimitExceeded,
+    /// UnsupportedSyntax is used when syntax is found that was turned off in the `ParseOptions`
+    UnsupportedSyntax(TextRange, LanguageFeature),
+    /// InvalidToken is used when the tokenizer couldn't make sense of the input
+    InvalidToken(TextRange, LexError),
+    /// DuplicatedAttr is used when an attribute is defined twice, e.g. `{ a = 1; a = 2; }`.
+    /// Contains the duplicate, the first definition and the attribute's path.
+    DuplicatedAttr(TextRange, TextRange, String),
+    /// InvalidLiteral is used when a number literal is out of range or isn't read by Nix
+    /// the way it looks, e.g. `99999999999999999999`
+    InvalidLiteral(TextRange, LiteralError),
 }
 
 impl fmt::Display for ParseError {
@@ -83,36 +93,281 @@ impl fmt::Display for ParseError {
                 )
             }
             ParseError::RecursionLimitExceeded => write!(f, "recursion limit exceeded"),
+            ParseError::InvalidToken(range, reason) => {
+                write!(
+                    f,
+                    "{} at {}..{}",
+                    reason,
+                    usize::from(range.start()),
+                    usize::from(range.end())
+                )
+            }
+            ParseError::DuplicatedAttr(range, first, path) => {
+                write!(
+                    f,
+                    "attribute `{}` at {}..{} is already defined at {}..{}",
+                    path,
+                    usize::from(range.start()),
+                    usize::from(range.end()),
+                    usize::from(first.start()),
+                    usize::from(first.end())
+                )
+            }
+            ParseError::UnsupportedSyntax(range, feature) => {
+                write!(
+                    f,
+                    "{} is not supported at {}..{}",
+                    feature,
+                    usize::from(range.start()),
+                    usize::from(range.end())
+                )
+            }
+            ParseError::InvalidLiteral(range, reason) => {
+                write!(
+                    f,
+                    "{} at {}..{}",
+                    reason,
+                    usize::from(range.start()),
+                    usize::from(range.end())
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for ParseError {}
 
+/// Turn the errors the tokenizer found into parse errors
+pub(crate) fn lex_errors(
+    errors: &[(TextRange, LexError)],
+) -> impl Iterator<Item = ParseError> + '_ {
+    errors.iter().map(|&(range, reason)| ParseError::InvalidToken(range, reason))
+}
+
+impl ParseError {
+    /// The position of the error, if it's known
+    pub(crate) fn range(&self) -> Option<TextRange> {
+        match self {
+            ParseError::Unexpected(range)
+            | ParseError::UnexpectedExtra(range)
+            | ParseError::UnexpectedWanted(_, range, _)
+            | ParseError::UnexpectedDoubleBind(range)
+            | ParseError::DuplicatedArgs(range, _)
+            | ParseError::UnsupportedSyntax(range, _)
+            | ParseError::InvalidToken(range, _)
+            | ParseError::InvalidLiteral(range, _) => Some(*range),
+            ParseError::DuplicatedAttr(range, first, _) => Some(range.cover(*first)),
+            ParseError::UnexpectedEOF
+            | ParseError::UnexpectedEOFWanted(_)
+            | ParseError::RecursionLimitExceeded => None,
+        }
+    }
+
+    /// Move the error to a different position, e.g. after an edit
+    pub(crate) fn map_range(&self, f: impl Fn(TextRange) -> TextRange) -> ParseError {
+        let mut err = self.clone();
+        match &mut err {
+            ParseError::Unexpected(range)
+            | ParseError::UnexpectedExtra(range)
+            | ParseError::UnexpectedWanted(_, range, _)
+            | ParseError::UnexpectedDoubleBind(range)
+            | ParseError::DuplicatedArgs(range, _)
+            | ParseError::UnsupportedSyntax(range, _)
+            | ParseError::InvalidToken(range, _)
+            | ParseError::InvalidLiteral(range, _) => *range = f(*range),
+            ParseError::DuplicatedAttr(range, first, _) => {
+                *range = f(*range);
+                *first = f(*first);
+            }
+            ParseError::UnexpectedEOF
+            | ParseError::UnexpectedEOFWanted(_)
+            | ParseError::RecursionLimitExceeded => (),
+        }
+        err
+    }
+}
+
+/// The reason a number literal is invalid
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LiteralError {
+    /// An integer that doesn't fit into Nix's 64-bit integers
+    IntegerOverflow,
+    /// A float that is too large or too small to be represented
+    FloatOutOfRange,
+    /// A float with leading zeros such as `01.5`, which Nix reads as `01` followed by `.5`
+    FloatLeadingZeros,
+    /// `0.` without digits after the dot, which Nix reads as `0` followed by `.`
+    FloatMissingFraction,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiteralError::IntegerOverflow => {
+                write!(f, "integer is larger than the maximum of {}", i64::MAX)
+            }
+            LiteralError::FloatOutOfRange => write!(f, "float is out of range"),
+            LiteralError::FloatLeadingZeros => write!(f, "float cannot have leading zeros"),
+            LiteralError::FloatMissingFraction => {
+                write!(f, "float needs digits after the `.`")
+            }
+        }
+    }
+}
+
+/// Syntax that only some versions of Nix accept
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LanguageFeature {
+    /// The deprecated `let { ...; body = ...; }` syntax
+    LegacyLet,
+    /// Unquoted URIs such as `https://nixos.org`, rejected by Nix's
+    /// `no-url-literals` experimental feature
+    UriLiteral,
+    /// `or` used as an identifier, such as in `f or`
+    OrAsIdent,
+    /// The `|>` and `<|` operators from Nix's `pipe-operators` experimental feature
+    PipeOperators,
+    /// Interpolation in paths, such as `./${name}.nix`, added in Nix 2.4
+    PathInterpolation,
+}
+
+impl fmt::Display for LanguageFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LanguageFeature::LegacyLet => write!(f, "legacy let syntax"),
+            LanguageFeature::UriLiteral => write!(f, "URI literal"),
+            LanguageFeature::OrAsIdent => write!(f, "`or` as an identifier"),
+            LanguageFeature::PipeOperators => write!(f, "pipe operator"),
+            LanguageFeature::PathInterpolation => write!(f, "interpolation in a path"),
+        }
+    }
+}
+
+/// Options for the dialect of Nix to parse. The default accepts all syntax
+/// the parser knows about, including experimental syntax.
+///
+/// Syntax that is turned off is still parsed into the same tree, but reported
+/// as a `ParseError::UnsupportedSyntax`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct ParseOptions {
+    /// Accept `let { ...; body = ...; }`
+    pub legacy_let: bool,
+    /// Accept unquoted URIs such as `https://nixos.org`
+    pub uri_literals: bool,
+    /// Accept `or` as an identifier when it follows an expression, such as in `f or`
+    pub or_as_ident: bool,
+    /// Accept the `|>` and `<|` operators
+    pub pipe_operators: bool,
+    /// Accept interpolation in paths, such as `./${name}.nix`
+    pub path_interpolation: bool,
+    /// How deeply expressions may be nested before the parser gives up with
+    /// `ParseError::RecursionLimitExceeded`, to avoid overflowing the stack
+    pub recursion_limit: u32,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            legacy_let: true,
+            uri_literals: true,
+            or_as_ident: true,
+            pipe_operators: true,
+            path_interpolation: true,
+            // Chosen somewhat arbitrarily
+            recursion_limit: 512,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Accept or reject `let { ...; body = ...; }`
+    pub fn legacy_let(mut self, enabled: bool) -> Self {
+        self.legacy_let = enabled;
+        self
+    }
+    /// Accept or reject unquoted URIs such as `https://nixos.org`
+    pub fn uri_literals(mut self, enabled: bool) -> Self {
+        self.uri_literals = enabled;
+        self
+    }
+    /// Accept or reject `or` as an identifier, such as in `f or`
+    pub fn or_as_ident(mut self, enabled: bool) -> Self {
+        self.or_as_ident = enabled;
+        self
+    }
+    /// Accept or reject the `|>` and `<|` operators
+    pub fn pipe_operators(mut self, enabled: bool) -> Self {
+        self.pipe_operators = enabled;
+        self
+    }
+    /// Accept or reject interpolation in paths, such as `./${name}.nix`
+    pub fn path_interpolation(mut self, enabled: bool) -> Self {
+        self.path_interpolation = enabled;
+        self
+    }
+    /// Set how deeply expressions may be nested
+    pub fn recursion_limit(mut self, limit: u32) -> Self {
+        self.recursion_limit = limit;
+        self
+    }
+    /// The syntax a release of Nix accepts without experimental features, with
+    /// the recursion limit left at its default. Experimental syntax like the
+    /// pipe operators can be turned on again with its own option.
+    pub fn for_version(version: LanguageVersion) -> Self {
+        let stable = Self::default().pipe_operators(false);
+        match version {
+            LanguageVersion::Nix2_3 => stable.path_interpolation(false),
+            LanguageVersion::Nix2_4 => stable,
+        }
+    }
+}
+
+impl From<LanguageVersion> for ParseOptions {
+    fn from(version: LanguageVersion) -> Self {
+        Self::for_version(version)
+    }
+}
+
+/// Releases of Nix that changed the syntax, to pick `ParseOptions` with
+/// `ParseOptions::for_version`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LanguageVersion {
+    /// Nix 2.3, without interpolation in paths
+    Nix2_3,
+    /// Nix 2.4 and later, which added interpolation in paths
+    Nix2_4,
+}
+
 struct Parser<'a, I>
 where
     I: Iterator<Item = Token<'a>>,
 {
     builder: GreenNodeBuilder<'static>,
     errors: Vec<ParseError>,
+    options: ParseOptions,
 
     trivia_buffer: Vec<Token<'a>>,
     buffer: VecDeque<Token<'a>>,
     iter: I,
     consumed: TextSize,
 
-    // Recursion depth, used for avoiding stack overflows. This may be incremented
-    // by any method as long as it is decremented when that method returns.
+    // Recursion depth, used for avoiding stack overflows. Only `nested` changes it.
     depth: u32,
+    max_depth: u32,
 }
 impl<'a, I> Parser<'a, I>
 where
     I: Iterator<Item = Token<'a>>,
 {
-    fn new(iter: I) -> Self {
+    fn new(iter: I, options: ParseOptions) -> Self {
         Self {
             builder: GreenNodeBuilder::new(),
             errors: Vec::new(),
+            options,
 
             trivia_buffer: Vec::with_capacity(1),
             buffer: VecDeque::with_capacity(1),
@@ -120,6 +375,7 @@ where
             consumed: TextSize::from(0),
 
             depth: 0,
+            max_depth: 0,
         }
     }
 
@@ -159,6 +415,33 @@ where
     fn finish_node(&mut self) {
         self.builder.finish_node();
     }
+    /// Report syntax that the options turned off. The syntax is still parsed normally.
+    fn check_feature(&mut self, enabled: bool, feature: LanguageFeature, start: TextSize) {
+        if !enabled {
+            let range = TextRange::new(start, self.get_text_position());
+            self.errors.push(ParseError::UnsupportedSyntax(range, feature));
+        }
+    }
+    /// Run `parse` one level deeper. Every recursion in the parser goes through
+    /// here, so that nesting can't overflow the stack.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Checkpoint) -> Checkpoint {
+        if self.depth >= self.options.recursion_limit {
+            self.errors.push(ParseError::RecursionLimitExceeded);
+            // Consume tokens to the end of the file. Erroring without bumping might cause
+            // infinite looping elsewhere.
+            self.start_error_node();
+            while self.peek().is_some() {
+                self.bump()
+            }
+            self.finish_error_node();
+            return self.checkpoint();
+        }
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        let out = parse(self);
+        self.depth -= 1;
+        out
+    }
     fn start_error_node(&mut self) -> TextSize {
         self.start_node(NODE_ERROR);
         self.get_text_position()
@@ -232,12 +515,30 @@ where
         }
     }
 
-    fn expect_ident(&mut self) {
-        if self.expect_peek_any(&[TOKEN_IDENT]).is_some() {
-            self.start_node(NODE_IDENT);
-            self.bump();
-            self.finish_node()
+    /// Wrap up the parse, reporting any tokens that weren't consumed
+    fn finish(&mut self) {
+        self.eat_trivia();
+        if self.peek().is_some() {
+            let start = self.start_error_node();
+            while self.peek().is_some() {
+                self.bump();
+            }
+            let end = self.finish_error_node();
+            self.errors.push(ParseError::UnexpectedExtra(TextRange::new(start, end)));
+            self.eat_trivia();
         }
+        self.builder.finish_node();
+    }
+
+    /// Parse an identifier, returning its name and position if there was one
+    fn expect_ident(&mut self) -> Option<(TextRange, String)> {
+        self.expect_peek_any(&[TOKEN_IDENT])?;
+        self.start_node(NODE_IDENT);
+        let start = self.get_text_position();
+        let name = self.peek_data().map(|&(_, s)| s.to_string()).unwrap_or_default();
+        self.bump();
+        self.finish_node();
+        Some((TextRange::new(start, self.get_text_position()), name))
     }
 
     fn parse_dynamic(&mut self) {
@@ -303,7 +604,21 @@ where
         }
         self.finish_node();
     }
-    fn parse_pattern(&mut self, bound: bool) {
+    /// Parse the rest of a pattern after its `{`. `bind` is the name bound
+    /// with `@` in front of the pattern, if any.
+    fn parse_pattern(&mut self, bound: bool, bind: Option<&str>) {
+        let mut names: Vec<String> = bind.into_iter().map(str::to_string).collect();
+        let mut check_duplicate =
+            |errors: &mut Vec<ParseError>, ident: Option<(TextRange, String)>| {
+                if let Some((range, name)) = ident {
+                    if names.contains(&name) {
+                        errors.push(ParseError::DuplicatedArgs(range, name));
+                    } else {
+                        names.push(name);
+                    }
+                }
+            };
+
         if self.peek().map(|t| t == T!['}']).unwrap_or(true) {
             self.bump();
         } else {
@@ -320,7 +635,8 @@ where
                     }
                     Some(TOKEN_IDENT) => {
                         self.start_node(NODE_PAT_ENTRY);
-                        self.expect_ident();
+                        let ident = self.expect_ident();
+                        check_duplicate(&mut self.errors, ident);
                         if let Some(T![?]) = self.peek() {
                             self.bump();
                             self.parse_expr();
@@ -345,14 +661,20 @@ where
             self.start_node(kind);
             let start = self.get_text_position();
             self.bump();
-            self.expect_ident();
+            let ident = self.expect_ident();
             let end = self.finish_error_node();
             if bound {
                 self.errors.push(ParseError::UnexpectedDoubleBind(TextRange::new(start, end)));
+            } else {
+                check_duplicate(&mut self.errors, ident);
             }
         }
     }
     fn parse_set(&mut self, until: SyntaxKind) {
+        self.parse_bindings(until);
+        self.bump(); // the final close, like '}'
+    }
+    fn parse_bindings(&mut self, until: SyntaxKind) {
         loop {
             match self.peek() {
                 None => break,
@@ -397,7 +719,6 @@ where
                 }
             }
         }
-        self.bump(); // the final close, like '}'
     }
 
     fn parse_simple(&mut self) -> Checkpoint {
@@ -458,7 +779,7 @@ where
 
                         self.start_node(NODE_PATTERN);
                         self.bump();
-                        self.parse_pattern(false);
+                        self.parse_pattern(false, None);
                         self.finish_node();
 
                         self.expect(T![:]);
@@ -479,7 +800,7 @@ where
                 self.start_node(NODE_LIST);
                 self.bump();
                 while self.peek().map(|t| t != T![']']).unwrap_or(false) {
-                    self.parse_simple();
+                    self.nested(Self::parse_simple);
                 }
                 self.bump();
                 self.finish_node();
@@ -487,8 +808,9 @@ where
             TOKEN_STRING_START => self.parse_string(),
             TOKEN_PATH => {
                 self.start_node(NODE_PATH);
+                let start = self.get_text_position();
                 self.bump();
-                let is_complex_path = self.peek().map_or(false, |t| t == TOKEN_INTERPOL_START);
+                let is_complex_path = self.peek() == Some(TOKEN_INTERPOL_START);
                 if is_complex_path {
                     loop {
                         match self.peek_raw().map(|(t, _)| t) {
@@ -503,16 +825,29 @@ where
                             _ => break,
                         }
                     }
+                    self.check_feature(
+                        self.options.path_interpolation,
+                        LanguageFeature::PathInterpolation,
+                        start,
+                    );
                 }
                 self.finish_node();
             }
             t if t.is_literal() => {
                 self.start_node(NODE_LITERAL);
+                let start = self.get_text_position();
                 self.bump();
                 self.finish_node();
+                if t == TOKEN_URI {
+                    self.check_feature(
+                        self.options.uri_literals,
+                        LanguageFeature::UriLiteral,
+                        start,
+                    );
+                }
             }
             TOKEN_IDENT => {
-                self.expect_ident();
+                let ident = self.expect_ident();
 
                 match self.peek() {
                     Some(T![:]) => {
@@ -531,7 +866,7 @@ where
                         self.finish_node(); // PatBind
 
                         self.expect(T!['{']);
-                        self.parse_pattern(true);
+                        self.parse_pattern(true, ident.as_ref().map(|(_, name)| name.as_str()));
                         self.finish_node(); // Pattern
 
                         self.expect(T![:]);
@@ -561,7 +896,7 @@ where
             self.parse_attrpath();
             if self.peek() == Some(T![or]) {
                 self.bump();
-                self.parse_simple();
+                self.nested(Self::parse_simple);
             }
             self.finish_node();
 
@@ -575,10 +910,12 @@ where
         } else if self.peek() == Some(T![or]) {
             self.start_node_at(checkpoint, NODE_APPLY);
             self.start_node(NODE_IDENT);
+            let start = self.get_text_position();
             let (_, s) = self.try_next().unwrap();
             self.manual_bump(s, TOKEN_IDENT);
             self.finish_node();
             self.finish_node();
+            self.check_feature(self.options.or_as_ident, LanguageFeature::OrAsIdent, start);
         }
 
         checkpoint
@@ -598,7 +935,7 @@ where
             let checkpoint = self.checkpoint();
             self.start_node(NODE_UNARY_OP);
             self.bump();
-            self.parse_negate();
+            self.nested(Self::parse_negate);
             self.finish_node();
             checkpoint
         } else {
@@ -609,7 +946,7 @@ where
         let checkpoint = next(self);
         if self.peek().map(|t| ops.contains(t)).unwrap_or(false) {
             self.start_node_at(checkpoint, NODE_BIN_OP);
-            self.bump();
+            self.bump_operator();
             next(self);
             self.finish_node();
         }
@@ -619,7 +956,7 @@ where
         let checkpoint = next(self);
         while self.peek().map(|t| ops.contains(t)).unwrap_or(false) {
             self.start_node_at(checkpoint, NODE_BIN_OP);
-            self.bump();
+            self.bump_operator();
             next(self);
             self.finish_node();
         }
@@ -633,12 +970,21 @@ where
         let checkpoint = next(self);
         if self.peek().map(|t| ops.contains(t)).unwrap_or(false) {
             self.start_node_at(checkpoint, NODE_BIN_OP);
-            self.bump();
-            self.parse_right_assoc(next, ops);
+            self.bump_operator();
+            self.nested(|parser| parser.parse_right_assoc(next, ops));
             self.finish_node();
         }
         checkpoint
     }
+    fn bump_operator(&mut self) {
+        self.eat_trivia();
+        let start = self.get_text_position();
+        let pipe = matches!(self.peek(), Some(T![|>] | T![<|]));
+        self.bump();
+        if pipe {
+            self.check_feature(self.options.pipe_operators, LanguageFeature::PipeOperators, start);
+        }
+    }
     fn parse_hasattr(&mut self) -> Checkpoint {
         let checkpoint = self.parse_negate();
         while self.peek().map(|t| t == T![?]).unwrap_or(false) {
@@ -663,7 +1009,7 @@ where
             let checkpoint = self.checkpoint();
             self.start_node(NODE_UNARY_OP);
             self.bump();
-            self.parse_invert();
+            self.nested(Self::parse_invert);
             self.finish_node();
             checkpoint
         } else {
@@ -688,29 +1034,53 @@ where
     fn parse_implication(&mut self) -> Checkpoint {
         self.parse_right_assoc(Self::parse_or, T![->] | ())
     }
+    /// `|>` is left-associative and `<|` right-associative. Like in Nix, they
+    /// can't be mixed without parentheses, so a chain stops at the other one.
+    fn parse_pipe(&mut self) -> Checkpoint {
+        let checkpoint = self.parse_implication();
+        match self.peek() {
+            Some(T![|>]) => {
+                while self.peek() == Some(T![|>]) {
+                    self.start_node_at(checkpoint, NODE_BIN_OP);
+                    self.bump_operator();
+                    self.parse_implication();
+                    self.finish_node();
+                }
+            }
+            Some(T![<|]) => {
+                self.start_node_at(checkpoint, NODE_BIN_OP);
+                self.bump_operator();
+                self.nested(|parser| {
+                    parser.parse_right_assoc(Self::parse_implication, T![<|] | ())
+                });
+                self.finish_node();
+            }
+            _ => return checkpoint,
+        }
+        if let Some(T![|>] | T![<|]) = self.peek() {
+            self.eat_trivia();
+            let start = self.start_error_node();
+            self.bump_operator();
+            self.nested(Self::parse_pipe);
+            let end = self.finish_error_node();
+            self.errors.push(ParseError::Unexpected(TextRange::new(start, end)));
+        }
+        checkpoint
+    }
     #[inline(always)]
     fn parse_math(&mut self) -> Checkpoint {
         // Always point this to the lowest-level math function there is
-        self.parse_implication()
+        self.parse_pipe()
     }
     /// Parse Nix code into an AST
     pub fn parse_expr(&mut self) -> Checkpoint {
-        // Limit chosen somewhat arbitrarily
-        if self.depth >= 512 {
-            self.errors.push(ParseError::RecursionLimitExceeded);
-            // Consume tokens to the end of the file. Erroring without bumping might cause
-            // infinite looping elsewhere.
-            self.start_error_node();
-            while self.peek().is_some() {
-                self.bump()
-            }
-            self.finish_error_node();
-            return self.checkpoint();
-        }
-        self.depth += 1;
-        let out = match self.peek() {
+        self.nested(Self::parse_expr_inner)
+    }
+    fn parse_expr_inner(&mut self) -> Checkpoint {
+        match self.peek() {
             Some(T![let]) => {
                 let checkpoint = self.checkpoint();
+                let start = self.get_text_position();
                 self.bump();
 
                 if self.peek() == Some(T!['{']) {
@@ -718,6 +1088,7 @@ where
                     self.bump();
                     self.parse_set(T!['}']);
                     self.finish_node();
+                    self.check_feature(self.options.legacy_let, LanguageFeature::LegacyLet, start);
                 } else {
                     self.start_node_at(checkpoint, NODE_LET_IN);
                     self.parse_set(T![in]);
@@ -759,9 +1130,7 @@ where
                 checkpoint
             }
             _ => self.parse_math(),
-        };
-        self.depth -= 1;
-        out
+        }
     }
 }
 
@@ -770,19 +1139,112 @@ pub fn parse<'s, I>(iter: I) -> (GreenNode, Vec<ParseError>)
 where
     I: Iterator<Item = Token<'s>>,
 {
-    let mut parser = Parser::new(iter);
+    parse_with_options(iter, ParseOptions::default())
+}
+
+/// Parse tokens into an AST, accepting the dialect selected by `options`
+pub fn parse_with_options<'s, I>(iter: I, options: ParseOptions) -> (GreenNode, Vec<ParseError>)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let (green, errors, _) = parse_entry(iter, EntryPoint::Root, options);
+    (green, errors)
+}
+
+/// Parse tokens into a single expression. Like with `parse`, the result is a
+/// `NODE_ROOT` that also holds the trivia and errors around the expression.
+pub fn parse_expr<'s, I>(iter: I) -> (GreenNode, Vec<ParseError>)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let (green, errors, _) = parse_entry(iter, EntryPoint::Expr, ParseOptions::default());
+    (green, errors)
+}
+
+/// Parse tokens into an attribute path, such as `services.nginx.enable`,
+/// inside of a `NODE_ROOT`
+pub fn parse_attrpath<'s, I>(iter: I) -> (GreenNode, Vec<ParseError>)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let (green, errors, _) = parse_entry(iter, EntryPoint::Attrpath, ParseOptions::default());
+    (green, errors)
+}
+
+/// Parse tokens into a lambda pattern, such as `{ a, b ? 1, ... }@args`,
+/// inside of a `NODE_ROOT`
+pub fn parse_pattern<'s, I>(iter: I) -> (GreenNode, Vec<ParseError>)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let (green, errors, _) = parse_entry(iter, EntryPoint::Pattern, ParseOptions::default());
+    (green, errors)
+}
+
+/// Parse tokens into the bindings of an attribute set without the surrounding
+/// braces, such as `a = 1; inherit b;`. The result is a `NODE_ATTR_SET` inside
+/// of a `NODE_ROOT`.
+pub fn parse_bindings<'s, I>(iter: I) -> (GreenNode, Vec<ParseError>)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let (green, errors, _) = parse_entry(iter, EntryPoint::Bindings, ParseOptions::default());
+    (green, errors)
+}
+
+/// What the tokens are parsed as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryPoint {
+    Root,
+    Expr,
+    Attrpath,
+    Pattern,
+    Bindings,
+}
+
+/// Parse tokens as `entry`. Also returns the deepest the parser was nested,
+/// which is what the recursion limit applies to.
+pub(crate) fn parse_entry<'s, I>(
+    iter: I,
+    entry: EntryPoint,
+    options: ParseOptions,
+) -> (GreenNode, Vec<ParseError>, u32)
+where
+    I: Iterator<Item = Token<'s>>,
+{
+    let mut parser = Parser::new(iter, options);
     parser.builder.start_node(NixLanguage::kind_to_raw(NODE_ROOT));
-    parser.parse_expr();
-    parser.eat_trivia();
-    if parser.peek().is_some() {
-        let start = parser.start_error_node();
-        while parser.peek().is_some() {
-            parser.bump();
-        }
-        let end = parser.finish_error_node();
-        parser.errors.push(ParseError::UnexpectedExtra(TextRange::new(start, end)));
-        parser.eat_trivia();
-    }
-    parser.builder.finish_node();
-    (parser.builder.finish(), parser.errors)
+    match entry {
+        EntryPoint::Root | EntryPoint::Expr => {
+            parser.parse_expr();
+        }
+        EntryPoint::Attrpath => parser.parse_attrpath(),
+        EntryPoint::Pattern => {
+            parser.start_node(NODE_PATTERN);
+            let bound = parser.peek() == Some(TOKEN_IDENT);
+            let mut bind = None;
+            if bound {
+                parser.start_node(NODE_PAT_BIND);
+                bind = parser.expect_ident();
+                parser.expect(T![@]);
+                parser.finish_node();
+            }
+            parser.expect(T!['{']);
+            parser.parse_pattern(bound, bind.as_ref().map(|(_, name)| name.as_str()));
+            parser.finish_node();
+        }
+        EntryPoint::Bindings => {
+            parser.start_node(NODE_ATTR_SET);
+            parser.parse_bindings(T!['}']);
+            parser.finish_node();
+        }
+    }
+    parser.finish();
+    let mut root = parser.builder.finish();
+    // `Parse::tree` needs an expression, even if there was nothing to parse
+    if entry == EntryPoint::Expr && root.children().all(|child| child.as_node().is_none()) {
+        let error = GreenNode::new(NixLanguage::kind_to_raw(NODE_ERROR), []);
+        root = root.insert_child(root.children().len(), error.into());
+    }
+    (root, parser.errors, parser.max_depth)
 }
@@ -0,0 +1,454 @@
+//! Incremental reparsing: apply a text edit to an existing parse, reusing the
+//! parts of the green tree the edit didn't touch.
+
+use std::ops::Range;
+
+use rowan::{GreenNode, GreenToken, Language, NodeOrToken, TextRange, TextSize};
+
+use crate::{
+    ast::Root,
+    parser::{self, EntryPoint, ParseError, ParseOptions},
+    tokenizer::{LexError, Tokenizer},
+    validation, NixLanguage, Parse,
+    SyntaxKind::{self, *},
+    SyntaxNode,
+};
+
+impl Parse<Root> {
+    /// Replace the text in `range` with `insert` and return the parse of the
+    /// edited document.
+    ///
+    /// Only the smallest whitespace, comment, string, attribute set, list or
+    /// parenthesized expression around the edit is parsed again, everything
+    /// else is shared with `self`. If none of them can be reparsed in isolation,
+    /// the whole document is parsed from scratch.
+    pub fn reparse(&self, range: TextRange, insert: &str) -> Parse<Root> {
+        self.incremental_reparse(range, insert).unwrap_or_else(|| self.full_reparse(range, insert))
+    }
+
+    fn full_reparse(&self, range: TextRange, insert: &str) -> Parse<Root> {
+        let mut text = self.syntax().to_string();
+        text.replace_range(std::ops::Range::<usize>::from(range), insert);
+        let tokenizer = Tokenizer::new(&text).with_invalid(self.invalid_after(range, insert));
+        Parse::from_tokens(tokenizer, EntryPoint::Root, self.options)
+    }
+
+    /// The ranges that were invalid UTF-8 in the input of `Root::parse_bytes`,
+    /// where they are after the edit. Their placeholders can't be told apart
+    /// from a `\0` in the text anymore.
+    fn invalid_after(&self, edit: TextRange, insert: &str) -> Vec<Range<usize>> {
+        let grow = |size: TextSize| usize::from(size + TextSize::of(insert) - edit.len());
+        let mut invalid: Vec<Range<usize>> = Vec::new();
+        let mut push = |start: usize, end: usize| match invalid.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => invalid.push(start..end),
+        };
+        for range in self.invalid_utf8() {
+            if range.start() < edit.start() {
+                push(range.start().into(), range.end().min(edit.start()).into());
+            }
+            if range.end() > edit.end() {
+                push(grow(range.start().max(edit.end())), grow(range.end()));
+            }
+        }
+        invalid
+    }
+
+    fn invalid_utf8(&self) -> impl Iterator<Item = TextRange> + '_ {
+        self.errors.iter().filter_map(|err| match err {
+            ParseError::InvalidToken(range, LexError::InvalidUtf8) => Some(*range),
+            _ => None,
+        })
+    }
+
+    fn incremental_reparse(&self, range: TextRange, insert: &str) -> Option<Parse<Root>> {
+        let root = self.syntax();
+        let (old_range, new_element, new_errors, depth) =
+            reparse_token(&root, range, insert, self.depth)
+                .or_else(|| reparse_node(&root, range, insert, self.options, self.depth))?;
+        // Relexing the element on its own would read the placeholders for invalid
+        // UTF-8 as text
+        if self.invalid_utf8().any(|invalid| invalid.intersect(old_range).is_some()) {
+            return None;
+        }
+
+        let green = match new_element {
+            NodeOrToken::Token(token) => match root.covering_element(old_range) {
+                NodeOrToken::Token(old) => old.replace_with(token),
+                NodeOrToken::Node(_) => return None,
+            },
+            NodeOrToken::Node(node) => match root.covering_element(old_range) {
+                NodeOrToken::Node(old) => old.replace_with(node),
+                NodeOrToken::Token(_) => return None,
+            },
+        };
+        let errors = merge_errors(&self.errors, new_errors, old_range, range, insert)?;
+        let new_root = SyntaxNode::new_root(green.clone());
+        let validation = revalidate(&self.errors, &new_root, old_range, range, insert)?;
+
+        Some(Parse::with_validation(green, errors, validation, self.options, self.entry, depth))
+    }
+}
+
+/// The range of the replaced element, its replacement, the errors in it and
+/// how deep the parser could be nested in the edited document
+type Reparsed = (TextRange, NodeOrToken<GreenNode, GreenToken>, Vec<ParseError>, u32);
+
+/// Relex a single whitespace or comment token.
+fn reparse_token(
+    root: &SyntaxNode,
+    range: TextRange,
+    insert: &str,
+    depth: u32,
+) -> Option<Reparsed> {
+    let token = root.covering_element(range).into_token()?;
+    if !matches!(token.kind(), TOKEN_WHITESPACE | TOKEN_COMMENT) {
+        return None;
+    }
+    // The tokenizer looks ahead into the first character of the next token, so
+    // it has to stay the same.
+    if range.start() <= token.text_range().start() {
+        return None;
+    }
+
+    let text = edit_text(&token.text_range(), token.text(), range, insert);
+    let mut tokens = Tokenizer::new(&text);
+    match (tokens.next(), tokens.next()) {
+        (Some((kind, s)), None) if kind == token.kind() && s.len() == text.len() => Some((
+            token.text_range(),
+            NodeOrToken::Token(GreenToken::new(NixLanguage::kind_to_raw(kind), &text)),
+            Vec::new(),
+            depth,
+        )),
+        _ => None,
+    }
+}
+
+/// Reparse the innermost delimited node around the edit. The parser was
+/// nested at most `depth` levels deep before.
+fn reparse_node(
+    root: &SyntaxNode,
+    range: TextRange,
+    insert: &str,
+    options: ParseOptions,
+    depth: u32,
+) -> Option<Reparsed> {
+    let node = root
+        .covering_element(range)
+        .ancestors()
+        .find(|node| closing_delimiter(node.kind()).is_some())?;
+    let node_range = node.text_range();
+    // Both delimiters have to survive the edit, otherwise the node can't be
+    // told apart from its surroundings anymore.
+    if range.start() <= node_range.start() || range.end() >= node_range.end() {
+        return None;
+    }
+
+    let text = edit_text(&node_range, &node.text().to_string(), range, insert);
+
+    // In isolation, a `}` without a matching `{` can't close an interpolation
+    // like it would in context.
+    let mut braces = 0i32;
+    let mut balanced = true;
+    let mut tokenizer = Tokenizer::new(&text);
+    let tokens = tokenizer.by_ref().inspect(|&(kind, _)| match kind {
+        TOKEN_L_BRACE => braces += 1,
+        TOKEN_R_BRACE => {
+            braces -= 1;
+            balanced &= braces >= 0;
+        }
+        _ => (),
+    });
+    let (green, mut errors, new_depth) = parser::parse_entry(tokens, EntryPoint::Root, options);
+    errors.extend(parser::lex_errors(tokenizer.errors()));
+    if !balanced {
+        return None;
+    }
+
+    let mut children = green.children();
+    let new_node = match (children.next(), children.next()) {
+        (Some(NodeOrToken::Node(new_node)), None) => new_node.to_owned(),
+        _ => return None,
+    };
+    let kind = NixLanguage::kind_from_raw(new_node.kind());
+    if kind != node.kind() {
+        return None;
+    }
+    let closed = match new_node.children().last() {
+        Some(NodeOrToken::Token(token)) => {
+            Some(NixLanguage::kind_from_raw(token.kind())) == closing_delimiter(kind)
+        }
+        _ => false,
+    };
+    if !closed || peeks_past_end(&new_node) {
+        return None;
+    }
+    // The recursion limit applies to the whole document, which parsing the node
+    // on its own doesn't know about. On its own, the parser needs one more
+    // level for the expression around the node, so the node started at most
+    // `depth - old_depth + 1` levels deep. That only holds if the old node
+    // parses the same on its own.
+    let old_text = node.text().to_string();
+    let (old_green, _, old_depth) =
+        parser::parse_entry(Tokenizer::new(&old_text), EntryPoint::Root, options);
+    if old_green.children().next().and_then(|child| child.into_node()) != Some(&*node.green()) {
+        return None;
+    }
+    let depth = (depth + new_depth).checked_sub(old_depth)?.max(depth);
+    if depth >= options.recursion_limit {
+        return None;
+    }
+
+    let offset = node_range.start();
+    let mut errors: Vec<_> = errors
+        .into_iter()
+        .map(|err| {
+            err.range()?;
+            Some(err.map_range(|range| range + offset))
+        })
+        .collect::<Option<_>>()?;
+    errors.sort_by_key(|err| err.range().map(|range| range.start()));
+
+    Some((node_range, NodeOrToken::Node(new_node), errors, depth))
+}
+
+/// Returns the token that ends a node of this kind, if it can be reparsed on its own.
+fn closing_delimiter(kind: SyntaxKind) -> Option<SyntaxKind> {
+    match kind {
+        NODE_ATTR_SET => Some(T!['}']),
+        NODE_LIST => Some(T![']']),
+        NODE_PAREN => Some(T![')']),
+        NODE_STRING => Some(TOKEN_STRING_END),
+        _ => None,
+    }
+}
+
+/// The parser decides between a set and a lambda pattern by looking at the two
+/// tokens after a `{`. For `{ }` the second one lies outside of the node.
+fn peeks_past_end(node: &GreenNode) -> bool {
+    let kind = |raw| NixLanguage::kind_from_raw(raw);
+    kind(node.kind()) == NODE_ATTR_SET
+        && node
+            .children()
+            .map(|child| kind(child.kind()))
+            .filter(|kind| !kind.is_trivia())
+            .take(3)
+            .eq([T!['{'], T!['}']])
+}
+
+fn edit_text(old_range: &TextRange, old_text: &str, range: TextRange, insert: &str) -> String {
+    let mut text = old_text.to_string();
+    let edit = range - old_range.start();
+    text.replace_range(std::ops::Range::<usize>::from(edit), insert);
+    text
+}
+
+/// Combine the errors outside of the reparsed element with the errors from
+/// reparsing it, moving everything after the edit to its new position.
+fn merge_errors(
+    old_errors: &[ParseError],
+    new_errors: Vec<ParseError>,
+    old_range: TextRange,
+    edit: TextRange,
+    insert: &str,
+) -> Option<Vec<ParseError>> {
+    let grow = |size: TextSize| size + TextSize::of(insert) - edit.len();
+
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    for err in old_errors.iter().filter(|err| !validation::is_validation_error(err)) {
+        // Errors without a position can't be attributed to a part of the tree.
+        let range = err.range()?;
+        if range.end() <= old_range.start() {
+            before.push(err.clone());
+        } else if range.start() >= old_range.end() {
+            after.push(
+                err.map_range(|range| TextRange::new(grow(range.start()), grow(range.end()))),
+            );
+        } else if old_range.contains_range(range) {
+            // replaced by the new errors
+        } else if range.contains_range(old_range) {
+            before.push(err.map_range(|range| TextRange::new(range.start(), grow(range.end()))));
+        } else {
+            return None;
+        }
+    }
+
+    before.extend(new_errors);
+    before.extend(after);
+    Some(before)
+}
+
+/// Run the checks that need the finished tree again where the edit could have
+/// changed their result, and move the errors they found elsewhere.
+fn revalidate(
+    old_errors: &[ParseError],
+    new_root: &SyntaxNode,
+    old_range: TextRange,
+    edit: TextRange,
+    insert: &str,
+) -> Option<Vec<ParseError>> {
+    let grow = |size: TextSize| size + TextSize::of(insert) - edit.len();
+    let shrink = |size: TextSize| size + edit.len() - TextSize::of(insert);
+    let new_range = TextRange::new(old_range.start(), grow(old_range.end()));
+    // Reparsed whitespace and comments don't change what the checks find
+    let scope =
+        new_root.covering_element(new_range).into_node().map(|node| validation::scope(&node));
+    let old_scope = scope.as_ref().map(|scope| {
+        let range = scope.text_range();
+        TextRange::new(range.start(), shrink(range.end()))
+    });
+
+    // Errors of sets around the edit can start before it and end after it
+    let shift = |size: TextSize| if size >= old_range.end() { grow(size) } else { size };
+    let mut errors = Vec::new();
+    for err in old_errors.iter().filter(|err| validation::is_validation_error(err)) {
+        let range = err.range()?;
+        if old_scope.is_some_and(|scope| scope.contains_range(range)) {
+            // replaced by checking the scope again
+        } else if range.end() <= old_range.start()
+            || range.start() >= old_range.end()
+            || range.contains_range(old_range)
+        {
+            errors.push(
+                err.map_range(|range| TextRange::new(shift(range.start()), shift(range.end()))),
+            );
+        } else {
+            return None;
+        }
+    }
+    if let Some(scope) = scope {
+        errors.extend(validation::validate(&scope));
+        errors.sort_by_key(|err| err.range().map(|range| range.start()));
+    }
+    Some(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use rowan::{TextRange, TextSize};
+
+    use crate::{parser::ParseOptions, Root};
+
+    fn check(before: &str, edit: (u32, u32), insert: &str, incremental: bool) {
+        let range = TextRange::new(TextSize::from(edit.0), TextSize::from(edit.1));
+        let old = Root::parse(before);
+
+        let mut after = before.to_string();
+        after.replace_range(std::ops::Range::<usize>::from(range), insert);
+        let expected = Root::parse(&after);
+
+        assert_eq!(old.incremental_reparse(range, insert).is_some(), incremental);
+        let actual = old.reparse(range, insert);
+        assert_eq!(actual.green, expected.green, "{:#?}", actual.syntax());
+        assert_eq!(actual.errors(), expected.errors());
+    }
+
+    #[test]
+    fn reparse_invalid_utf8() {
+        let check = |before: &[u8], edit: (u32, u32), insert: &str, incremental: bool| {
+            let range = TextRange::new(TextSize::from(edit.0), TextSize::from(edit.1));
+            let old = Root::parse_bytes(before);
+
+            let mut after = before.to_vec();
+            after.splice(std::ops::Range::<usize>::from(range), insert.bytes());
+            let expected = Root::parse_bytes(&after);
+
+            assert_eq!(old.incremental_reparse(range, insert).is_some(), incremental);
+            let actual = old.reparse(range, insert);
+            assert_eq!(actual.green, expected.green, "{:#?}", actual.syntax());
+            assert_eq!(actual.errors(), expected.errors());
+        };
+        check(b"\xff [ 1 ]", (4, 5), "2", true);
+        check(b"[ \"a\xff\" 1 ]", (7, 8), "2", false);
+        check(b"# \xff\xfe\n1", (2, 3), "", false);
+        check(b"\"\xffa\xff\"", (2, 3), "", false);
+        check(b"\"\xffa\xff\"", (2, 3), "b", false);
+    }
+
+    #[test]
+    fn reparse_whitespace() {
+        check("{ a =  1; }", (6, 7), "\n   ", true);
+        check("{ a =  1; }", (6, 7), "b", true);
+        check("a  b", (2, 2), "x", false);
+    }
+
+    #[test]
+    fn reparse_comment() {
+        check("[ # foo\n1 ]", (5, 5), "bar ", true);
+        check("# foo\n1", (3, 3), "\n", false);
+        check("/* foo */ 1", (3, 3), "*/", false);
+    }
+
+    #[test]
+    fn reparse_string() {
+        check(r#"{ a = "foo"; b = 2; }"#, (8, 9), "xx", true);
+        check(r#"{ a = "foo"; b = 2; }"#, (8, 8), "${x}", true);
+        check(r#"{ a = "foo"; b = 2; }"#, (8, 8), "\"", false);
+        check("{ a = ''\n  foo\n''; }", (11, 11), "${ bar }", true);
+    }
+
+    #[test]
+    fn reparse_set() {
+        check("let x = { a = 1; }; in x", (14, 15), "2 + 3", true);
+        check("let x = { a = 1; }; in x", (14, 15), "{ }", true);
+        check("let x = { a = 1; }; in x", (9, 17), "", false);
+        check("{ a = 1; }: a", (2, 8), "", false);
+    }
+
+    #[test]
+    fn reparse_list_and_paren() {
+        check("f [ 1 2 3 ] (x: x)", (6, 7), "(y)", true);
+        check("f [ 1 2 3 ] (x: x)", (16, 17), "x + 1", true);
+        check("f [ 1 2 3 ] (x: x)", (16, 17), "x ]", false);
+        check(r#""${ [ 1 ] }""#, (6, 7), "}", false);
+    }
+
+    #[test]
+    fn reparse_errors() {
+        check("[ (a@{ }@b: 1) [ 1 2 ] ]", (17, 18), "3 4", true);
+        check("[ (a@{ }@b: 1) [ 1 2 ] ]", (12, 13), "x", true);
+        check("[ (a@{ }@b: 1) [ 1 2 ] ]", (8, 10), "", true);
+        check("[ [ 1 2 ] (a@{ }@b: 1) ]", (5, 6), "", true);
+        check("[ [ 1 2 ] (a@{ }@b: 1) ]", (5, 6), "(", false);
+        check("[ [ 1 ./a ] 1.0e ]", (9, 9), "/", true);
+    }
+
+    #[test]
+    fn reparse_deep() {
+        let code = format!("[ {}1{} ]", "[ ".repeat(300), " ]".repeat(300));
+        check(&code, (600, 600), "[ [ 2 ] ]", true);
+        check(&code, (600, 601), &format!("{}2{}", "[ ".repeat(300), " ]".repeat(300)), false);
+    }
+
+    #[test]
+    fn reparse_recursion_limit() {
+        let options = ParseOptions::default().recursion_limit(8);
+        // Negations nest the parser without making the tree deeper
+        let old = Root::parse_with("[ (- - [ 1 ]) ]", options);
+        let range = TextRange::new(TextSize::from(9), TextSize::from(10));
+        let reparse = |insert: &str| {
+            let mut text = old.syntax().to_string();
+            text.replace_range(9..10, insert);
+            let expected = Root::parse_with(&text, options);
+            let actual = old.reparse(range, insert);
+            assert_eq!(actual.green, expected.green);
+            assert_eq!(actual.errors(), expected.errors());
+            old.incremental_reparse(range, insert).is_some()
+        };
+        assert!(reparse("2 3"));
+        assert!(reparse("[ 2 ]"));
+        assert!(!reparse("[ [ 2 ] ]"));
+        assert!(!reparse("[ [ [ [ 2 ] ] ] ]"));
+    }
+
+    #[test]
+    fn reparse_duplicated_attrs() {
+        check("{ a = [ 1 ]; a = 2; }", (8, 9), "3", true);
+        check("{ a = { b = 1; }; a.c = 2; }", (8, 9), "c", true);
+        check("{ a = { b = 1; }; a.c = 2; }", (8, 9), "d", true);
+        check("[ { a = 1; a = 2; } [ 1 ] { b = 1; b = 2; } ]", (23, 24), "1 2 0x", true);
+        check("{ a = 1; b = f { c = 1; }; a = 2; }", (17, 18), "cc = 2; c", true);
+        check("{ a = 1; a = 2; b = [ 1 ]; }", (22, 23), "99999999999999999999", true);
+    }
+}
@@ -0,0 +1,231 @@
+//! A JSON-friendly form of a parsed tree, for tools that aren't written in
+//! Rust. Needs the `serde` feature.
+//!
+//! With `serde_json`, the schema looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "root": {
+//!     "kind": "NODE_ROOT",
+//!     "range": [0, 6],
+//!     "children": [
+//!       {
+//!         "kind": "NODE_LIST",
+//!         "range": [0, 6],
+//!         "children": [
+//!           { "kind": "TOKEN_L_BRACK", "range": [0, 1], "text": "[" },
+//!           { "kind": "TOKEN_WHITESPACE", "range": [1, 2], "text": " " },
+//!           ...
+//!         ]
+//!       }
+//!     ]
+//!   },
+//!   "errors": [
+//!     { "kind": "UnexpectedEOF", "range": null, "message": "unexpected end of file" }
+//!   ]
+//! }
+//! ```
+//!
+//! - `version` is [`VERSION`], which changes whenever the schema does.
+//!   Reading a different version fails.
+//! - Nodes have `children`, tokens have `text`. `kind` is the name of the
+//!   [`SyntaxKind`].
+//! - `range` is the start and end offset in bytes. Ranges follow from the
+//!   text of the tokens and are ignored when reading.
+//! - Errors have the name of the [`ParseError`] variant as `kind`, its range
+//!   if it has one, and its message. They are only informational, reading
+//!   a tree gives back the green tree.
+
+use rowan::{GreenNode, GreenToken, Language, NodeOrToken, TextRange};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{parser::ParseError, NixLanguage, Parse, SyntaxKind, SyntaxNode, SyntaxToken};
+
+/// The version of the schema
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializedParse {
+    #[serde(deserialize_with = "version")]
+    pub version: u32,
+    pub root: SerializedNode,
+    pub errors: Vec<SerializedError>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializedNode {
+    pub kind: SyntaxKind,
+    pub range: [u32; 2],
+    pub children: Vec<SerializedElement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializedToken {
+    pub kind: SyntaxKind,
+    pub range: [u32; 2],
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SerializedElement {
+    Node(SerializedNode),
+    Token(SerializedToken),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SerializedError {
+    pub kind: String,
+    pub range: Option<[u32; 2]>,
+    pub message: String,
+}
+
+impl<T> From<&Parse<T>> for SerializedParse {
+    fn from(parse: &Parse<T>) -> Self {
+        SerializedParse {
+            version: VERSION,
+            root: SerializedNode::from(&parse.syntax()),
+            errors: parse.errors.iter().map(SerializedError::from).collect(),
+        }
+    }
+}
+
+impl SerializedParse {
+    /// The green tree, which is identical to the one that was serialized
+    pub fn to_green(&self) -> GreenNode {
+        self.root.to_green()
+    }
+}
+
+impl From<&SyntaxNode> for SerializedNode {
+    fn from(node: &SyntaxNode) -> Self {
+        SerializedNode {
+            kind: node.kind(),
+            range: range(node.text_range()),
+            children: node
+                .children_with_tokens()
+                .map(|child| match child {
+                    NodeOrToken::Node(node) => SerializedElement::Node((&node).into()),
+                    NodeOrToken::Token(token) => SerializedElement::Token((&token).into()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SerializedNode {
+    pub fn to_green(&self) -> GreenNode {
+        let children = self.children.iter().map(|child| match child {
+            SerializedElement::Node(node) => NodeOrToken::Node(node.to_green()),
+            SerializedElement::Token(token) => NodeOrToken::Token(token.to_green()),
+        });
+        GreenNode::new(NixLanguage::kind_to_raw(self.kind), children)
+    }
+}
+
+impl From<&SyntaxToken> for SerializedToken {
+    fn from(token: &SyntaxToken) -> Self {
+        SerializedToken {
+            kind: token.kind(),
+            range: range(token.text_range()),
+            text: token.text().to_string(),
+        }
+    }
+}
+
+impl SerializedToken {
+    pub fn to_green(&self) -> GreenToken {
+        GreenToken::new(NixLanguage::kind_to_raw(self.kind), &self.text)
+    }
+}
+
+impl From<&ParseError> for SerializedError {
+    fn from(err: &ParseError) -> Self {
+        let kind = match err {
+            ParseError::Unexpected(_) => "Unexpected",
+            ParseError::UnexpectedExtra(_) => "UnexpectedExtra",
+            ParseError::UnexpectedWanted(..) => "UnexpectedWanted",
+            ParseError::UnexpectedDoubleBind(_) => "UnexpectedDoubleBind",
+            ParseError::UnexpectedEOF => "UnexpectedEOF",
+            ParseError::UnexpectedEOFWanted(_) => "UnexpectedEOFWanted",
+            ParseError::DuplicatedArgs(..) => "DuplicatedArgs",
+            ParseError::RecursionLimitExceeded => "RecursionLimitExceeded",
+            ParseError::UnsupportedSyntax(..) => "UnsupportedSyntax",
+            ParseError::InvalidToken(..) => "InvalidToken",
+            ParseError::DuplicatedAttr(..) => "DuplicatedAttr",
+            ParseError::InvalidLiteral(..) => "InvalidLiteral",
+        };
+        SerializedError {
+            kind: kind.to_string(),
+            range: err.range().map(range),
+            message: err.to_string(),
+        }
+    }
+}
+
+fn range(range: TextRange) -> [u32; 2] {
+    [range.start().into(), range.end().into()]
+}
+
+fn version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version != VERSION {
+        return Err(serde::de::Error::custom(format!(
+            "unsupported schema version {}, expected {}",
+            version, VERSION
+        )));
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let parse = crate::Root::parse("let a = [ 1 \"${b}\" ]; in a.${c} or (x: x");
+        let json = serde_json::to_string(&SerializedParse::from(&parse)).unwrap();
+        let read: SerializedParse = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_green(), parse.syntax().green().into_owned());
+        assert_eq!(read.errors.len(), parse.errors().len());
+        assert_eq!(read.errors[0].kind, "UnexpectedEOF");
+        assert_eq!(read.errors[0].range, None);
+    }
+
+    #[test]
+    fn schema() {
+        let parse = crate::Root::parse("[ 1 ]");
+        let value = serde_json::to_value(SerializedParse::from(&parse)).unwrap();
+        let expected = serde_json::json!({
+            "version": VERSION,
+            "root": {
+                "kind": "NODE_ROOT",
+                "range": [0, 5],
+                "children": [{
+                    "kind": "NODE_LIST",
+                    "range": [0, 5],
+                    "children": [
+                        { "kind": "TOKEN_L_BRACK", "range": [0, 1], "text": "[" },
+                        { "kind": "TOKEN_WHITESPACE", "range": [1, 2], "text": " " },
+                        {
+                            "kind": "NODE_LITERAL",
+                            "range": [2, 3],
+                            "children": [{ "kind": "TOKEN_INTEGER", "range": [2, 3], "text": "1" }]
+                        },
+                        { "kind": "TOKEN_WHITESPACE", "range": [3, 4], "text": " " },
+                        { "kind": "TOKEN_R_BRACK", "range": [4, 5], "text": "]" }
+                    ]
+                }]
+            },
+            "errors": []
+        });
+        assert_eq!(value, expected);
+
+        let mut newer = expected;
+        newer["version"] = (VERSION + 1).into();
+        let err = serde_json::from_value::<SerializedParse>(newer).unwrap_err();
+        assert!(err.to_string().contains("unsupported schema version"));
+    }
+}
@@ -5,7 +5,10 @@ use rowan::ast::AstNode;
 
 use crate::{
     ast::{self, HasEntry},
-    tokenize, Root, SyntaxKind,
+    parser::{LanguageFeature, LanguageVersion, ParseError, ParseOptions},
+    tokenize,
+    tokenizer::{tokenize_ranged, LexError, Tokenizer, TokenizerState},
+    Root, SyntaxKind, TextRange,
 };
 
 #[test]
@@ -125,3 +128,307 @@ fn tokenizer_dir_tests() {
         actual
     })
 }
+
+#[test]
+fn token_ranges() {
+    let code = include_str!("../test_data/parser/success/interpolation.nix");
+    let tokens = tokenize_ranged(code);
+    let expected: Vec<_> = Root::parse(code)
+        .syntax()
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .map(|token| (token.kind(), token.text_range()))
+        .collect();
+    let actual: Vec<_> = tokens.iter().map(|token| (token.kind, token.range)).collect();
+    assert_eq!(actual, expected);
+    for token in tokens {
+        assert_eq!(&code[token.range], token.text);
+    }
+
+    let mut tokenizer = Tokenizer::new("a ~b").with_offset(10.into());
+    let ranges: Vec<_> = tokenizer.ranged().map(|token| token.range).collect();
+    assert_eq!(
+        ranges,
+        [
+            TextRange::new(10.into(), 11.into()),
+            TextRange::new(11.into(), 12.into()),
+            TextRange::new(12.into(), 14.into()),
+        ]
+    );
+    assert_eq!(
+        tokenizer.errors(),
+        [(TextRange::new(12.into(), 14.into()), LexError::HomeWithoutSlash)]
+    );
+}
+
+#[test]
+fn tokenizer_state() {
+    let code =
+        "{\n  a = ''\n    ${\n      { b = \"x\n y\"; }\n    }\n  '';\n  /* c\n  */ d = ./e;\n}\n";
+
+    // Lexing line by line gives each byte the same kind as lexing everything
+    let kinds = |tokens: Vec<(SyntaxKind, &str)>| -> Vec<SyntaxKind> {
+        tokens.into_iter().flat_map(|(kind, text)| text.bytes().map(move |_| kind)).collect()
+    };
+    let mut state = TokenizerState::default();
+    let mut states = Vec::new();
+    let mut by_line = Vec::new();
+    for line in code.split_inclusive('\n') {
+        let mut tokenizer = Tokenizer::with_state(line, state);
+        by_line.extend(tokenizer.by_ref());
+        state = tokenizer.state();
+        states.push(state.clone());
+    }
+    assert_eq!(kinds(by_line), kinds(tokenize(code)));
+    assert_eq!(state, TokenizerState::default());
+    assert_ne!(states[1], TokenizerState::default());
+    assert_ne!(states[1], states[2]);
+    assert_eq!(states[1], states[5]);
+
+    // Changing a line in the middle of a string doesn't change the state after it
+    let mut tokenizer = Tokenizer::with_state("    ${ }\n", states[1].clone());
+    tokenizer.by_ref().for_each(drop);
+    assert_eq!(tokenizer.state(), states[1]);
+}
+
+#[test]
+fn tokenizer_state_chunks() {
+    // Chunks can end anywhere in a comment or string, even between the `*`
+    // and `/` that close a comment
+    let code = "/* a **/ \"b\" /* c */ d";
+    let kinds = |tokens: Vec<(SyntaxKind, &str)>| -> Vec<SyntaxKind> {
+        tokens.into_iter().flat_map(|(kind, text)| text.bytes().map(move |_| kind)).collect()
+    };
+    for split in (2..=7).chain(10..=11).chain(15..=19) {
+        let mut state = TokenizerState::default();
+        let mut tokens = Vec::new();
+        for chunk in [&code[..split], &code[split..]] {
+            let mut tokenizer = Tokenizer::with_state(chunk, state);
+            tokens.extend(tokenizer.by_ref());
+            state = tokenizer.state();
+        }
+        assert_eq!(kinds(tokens), kinds(tokenize(code)), "split at {split}");
+        assert_eq!(state, TokenizerState::default(), "split at {split}");
+    }
+
+    // Finishing the last chunk reports what is still open
+    let mut tokenizer = Tokenizer::with_state("/* a *", TokenizerState::default());
+    tokenizer.by_ref().for_each(drop);
+    let mut tokenizer = Tokenizer::with_state(" b", tokenizer.state());
+    tokenizer.by_ref().for_each(drop);
+    tokenizer.finish();
+    assert_eq!(tokenizer.errors(), [(TextRange::empty(2.into()), LexError::UnterminatedComment)]);
+    assert_eq!(tokenizer.state(), TokenizerState::default());
+
+    let mut tokenizer = Tokenizer::with_state("x = ''\n", TokenizerState::default());
+    tokenizer.by_ref().for_each(drop);
+    tokenizer.finish();
+    assert_eq!(
+        tokenizer.errors(),
+        [(TextRange::new(4.into(), 6.into()), LexError::UnterminatedString { multiline: true })]
+    );
+}
+
+#[test]
+fn parse_bytes() {
+    let code = b"\xEF\xBB\xBF# comment\r\n[ 1 \"a\xFFb\" \xC3 ] # \xFE\r\n";
+    let parse = Root::parse_bytes(code);
+    assert_eq!(parse.syntax().text_range().len(), (code.len() as u32).into());
+    assert_eq!(
+        parse.errors(),
+        [
+            ParseError::InvalidToken(TextRange::new(20.into(), 21.into()), LexError::InvalidUtf8),
+            ParseError::InvalidToken(TextRange::new(24.into(), 25.into()), LexError::InvalidUtf8),
+            ParseError::InvalidToken(TextRange::new(30.into(), 31.into()), LexError::InvalidUtf8),
+        ]
+    );
+
+    let tokens: Vec<_> = parse
+        .syntax()
+        .descendants_with_tokens()
+        .filter_map(|element| element.into_token())
+        .map(|token| (token.kind(), token.text().to_string()))
+        .collect();
+    assert_eq!(tokens[0], (SyntaxKind::TOKEN_WHITESPACE, "\u{FEFF}".into()));
+    assert_eq!(tokens[1], (SyntaxKind::TOKEN_COMMENT, "# comment".into()));
+    assert_eq!(tokens[2], (SyntaxKind::TOKEN_WHITESPACE, "\r\n".into()));
+    assert!(tokens.contains(&(SyntaxKind::TOKEN_ERROR, "\0".into())));
+    assert!(tokens.contains(&(SyntaxKind::TOKEN_STRING_CONTENT, "a".into())));
+
+    let parse = Root::parse_bytes(b"\xEF\xBB\xBF{ a = 1; }\r\n");
+    assert!(parse.errors().is_empty());
+}
+
+#[test]
+fn fragments() {
+    let parse = ast::Expr::parse(" 1 + 2 ");
+    assert!(parse.errors().is_empty());
+    let op = ast::BinOp::try_from(parse.tree()).unwrap();
+    assert_eq!(op.operator(), Some(ast::BinOpKind::Add));
+    assert_eq!(parse.syntax().to_string(), " 1 + 2 ");
+
+    let parse = ast::Attrpath::parse("services.nginx.enable");
+    assert!(parse.errors().is_empty());
+    let attrs: Vec<_> = parse.tree().attrs().map(|attr| attr.to_string()).collect();
+    assert_eq!(attrs, ["services", "nginx", "enable"]);
+
+    let parse = ast::Attrpath::parse("a..b");
+    assert_eq!(
+        parse.errors(),
+        [ParseError::UnexpectedWanted(
+            SyntaxKind::TOKEN_DOT,
+            TextRange::new(2.into(), 3.into()),
+            [SyntaxKind::TOKEN_IDENT, SyntaxKind::TOKEN_OR].to_vec().into_boxed_slice()
+        )]
+    );
+
+    let parse = ast::Pattern::parse("{ a, b ? 1, ... }@args");
+    assert!(parse.errors().is_empty());
+    let pattern = parse.tree();
+    assert_eq!(pattern.pat_entries().count(), 2);
+    assert!(pattern.ellipsis_token().is_some());
+    assert_eq!(pattern.pat_bind().unwrap().ident().unwrap().to_string(), "args");
+
+    let parse = ast::Pattern::parse("args @ { a }");
+    assert!(parse.errors().is_empty());
+    assert_eq!(parse.tree().pat_bind().unwrap().ident().unwrap().to_string(), "args");
+
+    let parse = ast::Pattern::parse("args@{ args }");
+    assert_eq!(
+        parse.errors(),
+        [ParseError::DuplicatedArgs(TextRange::new(7.into(), 11.into()), "args".into())]
+    );
+
+    let parse = ast::AttrSet::parse_bindings("a = 1;\ninherit b;\n");
+    assert!(parse.errors().is_empty());
+    let set = parse.tree();
+    assert_eq!(set.entries().count(), 2);
+    assert!(set.l_curly_token().is_none());
+
+    let parse = ast::AttrSet::parse_bindings("a = 1; } b = 2;");
+    assert_eq!(parse.errors(), [ParseError::UnexpectedExtra(TextRange::new(7.into(), 15.into()))]);
+    assert_eq!(parse.syntax().to_string(), "a = 1; } b = 2;");
+
+    let parse = ast::Expr::parse(" ");
+    assert!(matches!(parse.tree(), ast::Expr::Error(_)));
+    assert_eq!(parse.errors(), [ParseError::UnexpectedEOF]);
+
+    // Junk after the fragment stays outside of its node
+    let parse = ast::Expr::parse(" a.b ) ");
+    assert_eq!(parse.syntax().to_string(), " a.b ) ");
+    assert_eq!(parse.errors(), [ParseError::UnexpectedExtra(TextRange::new(5.into(), 6.into()))]);
+    let select = ast::Select::try_from(parse.tree()).unwrap();
+    assert_eq!(select.to_string(), "a.b");
+    assert!(select.default_expr().is_none());
+
+    let parse = ast::Expr::parse("[ 1 ] ]");
+    let list = ast::List::try_from(parse.tree()).unwrap();
+    assert_eq!(list.items().map(|item| item.to_string()).collect::<Vec<_>>(), ["1"]);
+
+    let parse = ast::Expr::parse("\"a${b}\" c )");
+    let ast::Expr::Apply(apply) = parse.tree() else { panic!("should be an application") };
+    let ast::Expr::Str(s) = apply.lambda().unwrap() else { panic!("should be a string") };
+    assert_eq!(s.parts().count(), 2);
+}
+
+#[test]
+fn parse_options() {
+    let source = "let { body = f or; url = https://nixos.org; }";
+    let default = Root::parse(source);
+    assert!(default.errors().is_empty());
+
+    let options = ParseOptions::default().legacy_let(false).uri_literals(false).or_as_ident(false);
+    let parse = Root::parse_with(source, options);
+    assert_eq!(parse.green, default.green);
+    assert_eq!(
+        parse.errors(),
+        [
+            ParseError::UnsupportedSyntax(
+                TextRange::new(0.into(), 45.into()),
+                LanguageFeature::LegacyLet
+            ),
+            ParseError::UnsupportedSyntax(
+                TextRange::new(15.into(), 17.into()),
+                LanguageFeature::OrAsIdent
+            ),
+            ParseError::UnsupportedSyntax(
+                TextRange::new(25.into(), 42.into()),
+                LanguageFeature::UriLiteral
+            ),
+        ]
+    );
+
+    // Reparsing keeps the options
+    let parse = parse.reparse(TextRange::new(25.into(), 42.into()), "\"https://nixos.org\"");
+    assert_eq!(parse.errors().len(), 2);
+
+    let parse = Root::parse_with("x |> f", ParseOptions::default().pipe_operators(false));
+    assert_eq!(
+        parse.errors(),
+        [ParseError::UnsupportedSyntax(
+            TextRange::new(2.into(), 4.into()),
+            LanguageFeature::PipeOperators
+        )]
+    );
+}
+
+#[test]
+fn parse_options_entry_points() {
+    let options = ParseOptions::default().pipe_operators(false);
+    let unsupported = |range: (u32, u32), feature| {
+        ParseError::UnsupportedSyntax(TextRange::new(range.0.into(), range.1.into()), feature)
+    };
+    let pipe = |start: u32| [unsupported((start, start + 2), LanguageFeature::PipeOperators)];
+
+    assert_eq!(ast::Expr::parse_with("x |> f", options).errors(), pipe(2));
+    assert_eq!(Root::parse_bytes_with(b"x |> f", options).errors(), pipe(2));
+    assert_eq!(ast::Attrpath::parse_with("a.${x |> f}", options).errors(), pipe(6));
+    assert_eq!(ast::Pattern::parse_with("{ a ? x |> f }", options).errors(), pipe(8));
+    assert_eq!(ast::AttrSet::parse_bindings_with("a = x |> f;", options).errors(), pipe(6));
+    assert!(ast::Expr::parse("x |> f").errors().is_empty());
+}
+
+#[test]
+fn language_versions() {
+    let source = "[ ./${x}.nix (x |> f) ]";
+    let errors = |version| Root::parse_with(source, ParseOptions::from(version)).errors().to_vec();
+    let path = ParseError::UnsupportedSyntax(
+        TextRange::new(2.into(), 12.into()),
+        LanguageFeature::PathInterpolation,
+    );
+    let pipe = ParseError::UnsupportedSyntax(
+        TextRange::new(16.into(), 18.into()),
+        LanguageFeature::PipeOperators,
+    );
+
+    assert_eq!(errors(LanguageVersion::Nix2_3), [path.clone(), pipe.clone()]);
+    assert_eq!(errors(LanguageVersion::Nix2_4), [pipe]);
+
+    // Experimental syntax is opt-in on top of a version
+    let options = ParseOptions::for_version(LanguageVersion::Nix2_4).pipe_operators(true);
+    assert_eq!(Root::parse_with(source, options).errors(), []);
+}
+
+#[test]
+fn recursion_limit() {
+    let depth = 5_000;
+    for (open, close) in
+        [("[", "]"), ("-", ""), ("!", ""), ("a -> ", ""), ("a.b or ", ""), ("(", ")")]
+    {
+        let code = format!("{}x{}", open.repeat(depth), close.repeat(depth));
+        let parse = Root::parse(&code);
+        assert!(parse.errors().contains(&ParseError::RecursionLimitExceeded), "{}", open);
+        assert_eq!(parse.syntax().to_string(), code);
+        let last = parse
+            .syntax()
+            .descendants()
+            .filter(|node| node.kind() == SyntaxKind::NODE_ERROR)
+            .last();
+        assert_eq!(last.unwrap().text_range().end(), parse.syntax().text_range().end());
+    }
+
+    let code = "[ [ [ [ 1 ] ] ] ]";
+    assert!(Root::parse(code).errors().is_empty());
+    let parse = Root::parse_with(code, ParseOptions::default().recursion_limit(3));
+    assert!(parse.errors().contains(&ParseError::RecursionLimitExceeded));
+}
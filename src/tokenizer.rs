@@ -1,5 +1,9 @@
 //! The tokenizer: turns a string into tokens, such as numbers, strings, and keywords
 
+use std::{borrow::Cow, fmt, ops::Range};
+
+use rowan::{TextRange, TextSize};
+
 use crate::SyntaxKind::{self, *};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -21,9 +25,12 @@ fn is_valid_uri_char(c: char) -> bool {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum Context {
     StringBody { multiline: bool },
+    // Whether the comment so far ends in a `*`, which a `/` in the next chunk closes
+    Comment { star: bool },
     StringEnd,
     Interpol { brackets: u32 },
     InterpolStart,
@@ -46,24 +53,225 @@ impl Eq for State<'_> {}
 
 pub type Token<'a> = (SyntaxKind, &'a str);
 
+/// A token together with its position in the input
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RangedToken<'a> {
+    pub kind: SyntaxKind,
+    pub text: &'a str,
+    pub range: TextRange,
+}
+
+/// The reason the tokenizer produced a `TOKEN_ERROR`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LexError {
+    /// A string is missing its closing quotes
+    UnterminatedString { multiline: bool },
+    /// A `/*` comment is missing its `*/`
+    UnterminatedComment,
+    /// A path ends with `/`, such as `./foo/`
+    TrailingSlash,
+    /// A `~` isn't followed by `/`
+    HomeWithoutSlash,
+    /// A `<...>` path contains characters that aren't allowed
+    InvalidStorePath,
+    /// A float's exponent has no digits, such as `1.0e`
+    MissingExponent,
+    /// A character that can't start any token
+    UnexpectedChar(char),
+    /// Bytes that aren't valid UTF-8, when parsing bytes
+    InvalidUtf8,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedString { multiline: false } => {
+                write!(f, "unterminated string starting here")
+            }
+            LexError::UnterminatedString { multiline: true } => {
+                write!(f, "unterminated multi-line string starting here")
+            }
+            LexError::UnterminatedComment => write!(f, "unterminated comment starting here"),
+            LexError::TrailingSlash => write!(f, "path cannot end with a slash"),
+            LexError::HomeWithoutSlash => write!(f, "`~` must be followed by `/`"),
+            LexError::InvalidStorePath => write!(f, "invalid `<...>` path"),
+            LexError::MissingExponent => write!(f, "exponent has no digits"),
+            LexError::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            LexError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+        }
+    }
+}
+
+/// Stands in for invalid UTF-8, so that all offsets stay the same
+const INVALID_PLACEHOLDER: char = '\0';
+
+/// Turn bytes into a string that can be tokenized, by replacing every byte that
+/// isn't valid UTF-8 with a placeholder. Returns the ranges of the replaced bytes.
+pub(crate) fn sanitize(mut bytes: &[u8]) -> (Cow<'_, str>, Vec<Range<usize>>) {
+    let mut output = String::new();
+    let mut invalid: Vec<Range<usize>> = Vec::new();
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(rest) if invalid.is_empty() => return (Cow::Borrowed(rest), invalid),
+            Ok(rest) => {
+                output.push_str(rest);
+                return (Cow::Owned(output), invalid);
+            }
+            Err(err) => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                output.push_str(std::str::from_utf8(valid).unwrap());
+                let len = err.error_len().unwrap_or(rest.len());
+                let start = output.len();
+                output.extend(std::iter::repeat_n(INVALID_PLACEHOLDER, len));
+                match invalid.last_mut() {
+                    Some(last) if last.end == start => last.end += len,
+                    _ => invalid.push(start..start + len),
+                }
+                bytes = &rest[len..];
+            }
+        }
+    }
+}
+
 /// A convenience function for tokenizing the given input
 pub fn tokenize(input: &str) -> Vec<Token<'_>> {
     Tokenizer::new(input).collect()
 }
 
+/// Like `tokenize`, but also returns the range of every token
+pub fn tokenize_ranged(input: &str) -> Vec<RangedToken<'_>> {
+    Tokenizer::new(input).ranged().collect()
+}
+
+/// Where the tokenizer is in the middle of a multi-token construct, such as
+/// a string or an interpolation. Saving it at the end of a line and passing it
+/// to `Tokenizer::with_state` later continues lexing from that line, and two
+/// states compare equal if the tokenizer would continue the same way.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenizerState {
+    ctx: Vec<Context>,
+}
+
 /// The tokenizer. You may want to use the `tokenize` convenience function from this module instead.
 pub struct Tokenizer<'a> {
     ctx: Vec<Context>,
     state: State<'a>,
+    // Added to all ranges, for input that is a slice of a larger document
+    base: TextSize,
+    // Positions of the strings that are currently open, if known
+    strings: Vec<Option<TextSize>>,
+    // The input is a chunk of a document, and may end inside of a string or
+    // comment that continues in the next chunk
+    chunk: bool,
+    // The reason for the `TOKEN_ERROR` that is being lexed, and its range if it
+    // isn't the token itself
+    pending: Option<(LexError, Option<TextRange>)>,
+    errors: Vec<(TextRange, LexError)>,
+    // Ranges of the input that were invalid UTF-8, see `sanitize`
+    invalid: Vec<Range<usize>>,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Self {
-        Self { ctx: Vec::new(), state: State { input, offset: 0 } }
+        Self {
+            ctx: Vec::new(),
+            state: State { input, offset: 0 },
+            base: TextSize::from(0),
+            strings: Vec::new(),
+            chunk: false,
+            pending: None,
+            errors: Vec::new(),
+            invalid: Vec::new(),
+        }
+    }
+
+    /// Continue lexing from `state`, which was saved at the end of the previous
+    /// chunk of the document, usually a line including its `\n`. The end of
+    /// `input` isn't treated as the end of the document: strings and comments
+    /// that are still open produce their tokens so far and stay open in
+    /// `state()`, instead of being reported as unterminated. Call `finish`
+    /// after the last chunk to report them.
+    pub fn with_state(input: &'a str, state: TokenizerState) -> Self {
+        let strings = state
+            .ctx
+            .iter()
+            .filter(|ctx| matches!(ctx, Context::StringBody { .. } | Context::StringEnd))
+            .map(|_| None)
+            .collect();
+        Self { ctx: state.ctx, strings, chunk: true, ..Self::new(input) }
+    }
+
+    /// The current state, to continue from with `with_state`
+    pub fn state(&self) -> TokenizerState {
+        TokenizerState { ctx: self.ctx.clone() }
+    }
+
+    /// End the document after the tokens of its last chunk, reporting a string
+    /// or comment that is still open in `errors()` like the end of the input
+    /// would without `with_state`. Its range is where it starts if that was in
+    /// this chunk, and the end of the input otherwise.
+    pub fn finish(&mut self) {
+        let end = TextRange::empty(self.text_size(self.state.input.len()));
+        let error = match self.ctx.last() {
+            Some(Context::Comment { .. }) => Some((end, LexError::UnterminatedComment)),
+            Some(&Context::StringBody { multiline }) => {
+                let len = TextSize::from(if multiline { 2 } else { 1 });
+                let start = self.strings.last().copied().flatten();
+                let range = start.map_or(end, |start| TextRange::at(start, len));
+                Some((range, LexError::UnterminatedString { multiline }))
+            }
+            _ => None,
+        };
+        self.errors.extend(error);
+        self.ctx.clear();
+        self.strings.clear();
+    }
+
+    /// Report the `invalid` ranges of the input, as returned by `sanitize`,
+    /// as `LexError::InvalidUtf8` errors
+    pub(crate) fn with_invalid(mut self, invalid: Vec<Range<usize>>) -> Self {
+        self.invalid = invalid;
+        self
+    }
+
+    /// Report ranges relative to `offset` instead of the start of the input,
+    /// for when the input starts at `offset` in a larger document
+    pub fn with_offset(mut self, offset: TextSize) -> Self {
+        self.base = offset;
+        self
+    }
+
+    /// Return the next token along with its range
+    pub fn next_ranged(&mut self) -> Option<RangedToken<'a>> {
+        let start = self.text_size(self.state.offset);
+        let (kind, text) = Iterator::next(self)?;
+        Some(RangedToken { kind, text, range: TextRange::at(start, TextSize::of(text)) })
+    }
+
+    /// An iterator over the remaining tokens along with their ranges. The
+    /// tokenizer can still be used afterwards, for example to get its errors.
+    pub fn ranged(&mut self) -> impl Iterator<Item = RangedToken<'a>> + '_ {
+        std::iter::from_fn(move || self.next_ranged())
+    }
+
+    /// The reasons for all `TOKEN_ERROR`s returned so far
+    pub fn errors(&self) -> &[(TextRange, LexError)] {
+        &self.errors
     }
 }
 
 impl Tokenizer<'_> {
+    fn text_size(&self, offset: usize) -> TextSize {
+        self.base + TextSize::from(offset as u32)
+    }
+    /// The length of the invalid UTF-8 at the current offset, if any
+    fn invalid_len(&self) -> Option<usize> {
+        let offset = self.state.offset;
+        let i = self.invalid.binary_search_by_key(&offset, |range| range.start).ok()?;
+        Some(self.invalid[i].len())
+    }
     fn remaining(&self) -> &str {
         &self.state.input[self.state.offset..]
     }
@@ -97,6 +305,19 @@ impl Tokenizer<'_> {
         self.ctx.pop();
     }
 
+    fn error(&mut self, reason: LexError) -> SyntaxKind {
+        self.pending = Some((reason, None));
+        TOKEN_ERROR
+    }
+    fn unterminated_string(&mut self, multiline: bool) -> SyntaxKind {
+        let range = self.strings.last().copied().flatten().map(|start| {
+            let len = if multiline { 2 } else { 1 };
+            TextRange::at(start, TextSize::from(len))
+        });
+        self.pending = Some((LexError::UnterminatedString { multiline }, range));
+        TOKEN_ERROR
+    }
+
     fn consume<F>(&mut self, mut f: F) -> usize
     where
         F: FnMut(char) -> bool,
@@ -109,10 +330,16 @@ impl Tokenizer<'_> {
     fn next_string(&mut self, multiline: bool) -> SyntaxKind {
         loop {
             let start = self.state;
+            if self.invalid_len().is_some() {
+                return TOKEN_STRING_CONTENT;
+            }
             match self.next() {
+                None if self.chunk => return TOKEN_STRING_CONTENT,
                 None => {
+                    let token = self.unterminated_string(multiline);
                     self.pop_ctx(Context::StringBody { multiline });
-                    return TOKEN_ERROR;
+                    self.strings.pop();
+                    return token;
                 }
                 Some('"') if !multiline => {
                     self.state = start;
@@ -120,33 +347,34 @@ impl Tokenizer<'_> {
                     self.push_ctx(Context::StringEnd);
                     return TOKEN_STRING_CONTENT;
                 }
-                Some('\\') if !multiline => match self.next() {
-                    None => return TOKEN_ERROR,
-                    Some(_) => (),
-                },
+                Some('\\') if !multiline => {
+                    if self.next().is_none() {
+                        return self.unterminated_string(multiline);
+                    }
+                }
 
                 Some('\'') if multiline => match self.peek() {
-                    None => return TOKEN_ERROR,
-                    Some('\'') => match {
+                    None => return self.unterminated_string(multiline),
+                    Some('\'') => {
                         self.next();
-                        self.peek()
-                    } {
-                        Some('\'') | Some('$') => {
-                            self.next().unwrap();
-                        }
-                        Some('\\') => {
-                            self.next().unwrap();
-                            if let None = self.next() {
-                                return TOKEN_ERROR;
+                        match self.peek() {
+                            Some('\'') | Some('$') => {
+                                self.next().unwrap();
+                            }
+                            Some('\\') => {
+                                self.next().unwrap();
+                                if self.next().is_none() {
+                                    return self.unterminated_string(multiline);
+                                }
+                            }
+                            _ => {
+                                self.state = start;
+                                self.pop_ctx(Context::StringBody { multiline: true });
+                                self.push_ctx(Context::StringEnd);
+                                return TOKEN_STRING_CONTENT;
                             }
                         }
-                        _ => {
-                            self.state = start;
-                            self.pop_ctx(Context::StringBody { multiline: true });
-                            self.push_ctx(Context::StringEnd);
-                            return TOKEN_STRING_CONTENT;
-                        }
-                    },
+                    }
                     Some(_) => (),
                 },
 
@@ -166,12 +394,35 @@ impl Tokenizer<'_> {
         }
     }
 
+    fn next_comment(&mut self, start: State, mut star: bool) -> SyntaxKind {
+        loop {
+            match self.peek() {
+                None if self.chunk => {
+                    self.push_ctx(Context::Comment { star });
+                    return TOKEN_COMMENT;
+                }
+                None => {
+                    let range = TextRange::at(self.text_size(start.offset), 2.into());
+                    self.pending = Some((LexError::UnterminatedComment, Some(range)));
+                    return TOKEN_ERROR;
+                }
+                Some('/') if star => {
+                    self.next().unwrap();
+                    return TOKEN_COMMENT;
+                }
+                _ => (),
+            }
+            self.consume(|c| c != '*');
+            star = self.next().is_some(); // consume the '*', if any
+        }
+    }
+
     fn check_path_since(&mut self, past: State) -> SyntaxKind {
         self.consume(is_valid_path_char);
         if self.remaining().starts_with("${") {
             self.ctx.push(Context::InterpolStart);
         } else if self.str_since(past).ends_with('/') {
-            return TOKEN_ERROR;
+            return self.error(LexError::TrailingSlash);
         } else {
             self.pop_ctx(Context::Path);
         }
@@ -181,6 +432,11 @@ impl Tokenizer<'_> {
     fn next_inner(&mut self) -> Option<SyntaxKind> {
         let start = self.state;
 
+        if let Some(len) = self.invalid_len() {
+            self.state.offset += len;
+            return Some(self.error(LexError::InvalidUtf8));
+        }
+
         // Handle already started multi-token
         loop {
             match self.ctx.last() {
@@ -197,67 +453,81 @@ impl Tokenizer<'_> {
                     if self.starts_with_bump("${") {
                         self.ctx.push(Context::Interpol { brackets: 0 });
                         return Some(TOKEN_INTERPOL_START);
-                    } else if self.peek().map_or(false, is_valid_path_char) {
+                    } else if self.peek().is_some_and(is_valid_path_char) {
                         return Some(self.check_path_since(start));
                     } else {
                         self.pop_ctx(Context::Path);
                     }
                 }
+                Some(Context::StringBody { .. } | Context::Comment { .. })
+                    if self.chunk && self.remaining().is_empty() =>
+                {
+                    // Stays open for the next chunk
+                    return None;
+                }
+                Some(&Context::Comment { star }) => {
+                    self.pop_ctx(Context::Comment { star });
+                    return Some(self.next_comment(start, star));
+                }
                 Some(Context::StringBody { multiline }) => {
                     let token = self.next_string(*multiline);
                     // skip empty stuff
                     if self.state == start {
+                        self.pending = None;
                         continue;
                     }
                     return Some(token);
                 }
                 Some(Context::StringEnd) => {
                     self.pop_ctx(Context::StringEnd);
+                    let multiline = self.peek() == Some('\'');
                     let status = match self.peek() {
                         Some('"') => {
                             self.next().unwrap();
                             true
                         }
-                        Some('\'') => match {
+                        Some('\'') => {
                             self.next().unwrap();
-                            self.peek()
-                        } {
-                            Some('\'') => {
-                                self.next().unwrap();
-                                true
+                            match self.peek() {
+                                Some('\'') => {
+                                    self.next().unwrap();
+                                    true
+                                }
+                                _ => false,
                             }
-                            _ => false,
-                        },
+                        }
                         _ => false,
                     };
-                    return Some(if status { TOKEN_STRING_END } else { TOKEN_ERROR });
+                    let token =
+                        if status { TOKEN_STRING_END } else { self.unterminated_string(multiline) };
+                    self.strings.pop();
+                    return Some(token);
                 }
                 _ => (),
             }
             break;
         }
 
+        // A byte order mark can only appear at the start of a document
+        if start.offset == 0 && self.base == TextSize::from(0) && self.starts_with_bump("\u{FEFF}")
+        {
+            self.consume(char::is_whitespace);
+            return Some(TOKEN_WHITESPACE);
+        }
         if self.consume(char::is_whitespace) > 0 {
             return Some(TOKEN_WHITESPACE);
         }
 
         if self.peek() == Some('#') {
             self.consume(|c| c != '\n');
+            // Leave the `\r` of a `\r\n` line ending to the whitespace
+            if self.str_since(start).ends_with('\r') {
+                self.state.offset -= 1;
+            }
             return Some(TOKEN_COMMENT);
         }
         if self.starts_with_bump("/*") {
-            loop {
-                self.consume(|c| c != '*');
-                self.next(); // consume the '*', if any
-                match self.peek() {
-                    None => return Some(TOKEN_ERROR),
-                    Some('/') => {
-                        self.next().unwrap();
-                        return Some(TOKEN_COMMENT);
-                    }
-                    _ => (),
-                }
-            }
+            return Some(self.next_comment(start, false));
         }
 
         if self.starts_with_bump("...") {
@@ -295,7 +565,7 @@ impl Tokenizer<'_> {
 
         if c == '~' || kind == Some(IdentType::Path) {
             return Some(if c == '~' && self.next() != Some('/') {
-                TOKEN_ERROR
+                self.error(LexError::HomeWithoutSlash)
             } else {
                 self.push_ctx(Context::Path);
                 self.check_path_since(start)
@@ -336,8 +606,8 @@ impl Tokenizer<'_> {
             ':' => TOKEN_COLON,
             ',' => TOKEN_COMMA,
             '.' => {
-                if self.peek().map_or(false, |x| ('0'..='9').contains(&x)) {
-                    self.consume(|c| ('0'..='9').contains(&c));
+                if self.peek().is_some_and(|x| x.is_ascii_digit()) {
+                    self.consume(|c| c.is_ascii_digit());
                     self.consume_scientific()
                 } else {
                     TOKEN_DOT
@@ -367,7 +637,7 @@ impl Tokenizer<'_> {
             '<' if kind == Some(IdentType::Store) => {
                 self.consume(is_valid_path_char);
                 if self.next() != Some('>') {
-                    TOKEN_ERROR
+                    self.error(LexError::InvalidStorePath)
                 } else {
                     TOKEN_PATH
                 }
@@ -380,6 +650,14 @@ impl Tokenizer<'_> {
                 self.next().unwrap();
                 TOKEN_OR_OR
             }
+            '|' if self.peek() == Some('>') => {
+                self.next().unwrap();
+                TOKEN_PIPE_RIGHT
+            }
+            '<' if self.peek() == Some('|') => {
+                self.next().unwrap();
+                TOKEN_PIPE_LEFT
+            }
             '<' if self.peek() == Some('=') => {
                 self.next().unwrap();
                 TOKEN_LESS_OR_EQ
@@ -427,25 +705,27 @@ impl Tokenizer<'_> {
                 }
             }
             '"' => {
+                self.strings.push(Some(self.text_size(start.offset)));
                 self.push_ctx(Context::StringBody { multiline: false });
                 TOKEN_STRING_START
             }
             '\'' if self.peek() == Some('\'') => {
                 self.next().unwrap();
+                self.strings.push(Some(self.text_size(start.offset)));
                 self.push_ctx(Context::StringBody { multiline: true });
                 TOKEN_STRING_START
             }
             '0'..='9' => {
-                self.consume(|c| ('0'..='9').contains(&c));
+                self.consume(|c| c.is_ascii_digit());
                 if self.peek() == Some('.') {
                     self.next().unwrap();
-                    self.consume(|c| ('0'..='9').contains(&c));
+                    self.consume(|c| c.is_ascii_digit());
                     self.consume_scientific()
                 } else {
                     TOKEN_INTEGER
                 }
             }
-            _ => TOKEN_ERROR,
+            c => self.error(LexError::UnexpectedChar(c)),
         })
     }
 
@@ -455,8 +735,8 @@ impl Tokenizer<'_> {
             if self.peek() == Some('-') || self.peek() == Some('+') {
                 self.next().unwrap();
             }
-            if self.consume(|c| ('0'..='9').contains(&c)) == 0 {
-                return TOKEN_ERROR;
+            if self.consume(|c| c.is_ascii_digit()) == 0 {
+                return self.error(LexError::MissingExponent);
             }
         }
         TOKEN_FLOAT
@@ -467,6 +747,24 @@ impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         let start = self.state;
-        self.next_inner().map(|syntax_kind| (syntax_kind, self.str_since(start)))
+        let kind = self.next_inner()?;
+        if let Some((reason, range)) = self.pending.take() {
+            let range = range.unwrap_or_else(|| {
+                TextRange::new(self.text_size(start.offset), self.text_size(self.state.offset))
+            });
+            self.errors.push((range, reason));
+        } else if !self.invalid.is_empty() {
+            // Invalid UTF-8 that ended up inside of a comment or string
+            let first = self.invalid.partition_point(|range| range.end <= start.offset);
+            for i in first..self.invalid.len() {
+                let Range { start, end } = self.invalid[i].clone();
+                if start >= self.state.offset {
+                    break;
+                }
+                let range = TextRange::new(self.text_size(start), self.text_size(end));
+                self.errors.push((range, LexError::InvalidUtf8));
+            }
+        }
+        Some((kind, self.str_since(start)))
     }
 }
@@ -0,0 +1,243 @@
+//! Checks that Nix performs while parsing, but that need the finished tree:
+//! attributes that are defined more than once and invalid number literals.
+
+use std::collections::{hash_map::Entry as MapEntry, HashMap};
+
+use rowan::{ast::AstNode, TextRange};
+
+use crate::{
+    ast::{self, HasEntry},
+    parser::{LiteralError, ParseError},
+    SyntaxKind::{self, *},
+    SyntaxNode,
+};
+
+/// Run all checks over the tree, or a part of it returned by [`scope`].
+/// Errors are sorted by position.
+pub(crate) fn validate(root: &SyntaxNode) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    for node in root.descendants() {
+        // Sets that are the value of a binding are checked together with it,
+        // since they can be merged with other definitions of the same name.
+        if is_binding_value(&node) {
+            continue;
+        }
+        if let Some(set) = ast::AttrSet::cast(node.clone()) {
+            check_entries(&set, &[], &mut errors);
+        } else if let Some(let_in) = ast::LetIn::cast(node.clone()) {
+            check_entries(&let_in, &[], &mut errors);
+        } else if let Some(let_legacy) = ast::LegacyLet::cast(node) {
+            check_entries(&let_legacy, &[], &mut errors);
+        }
+    }
+    for token in root.descendants_with_tokens().filter_map(|element| element.into_token()) {
+        if let Some(reason) = check_literal(token.kind(), token.text()) {
+            errors.push(ParseError::InvalidLiteral(token.text_range(), reason));
+        }
+    }
+    errors.sort_by_key(|err| err.range().map(|range| range.start()));
+    errors
+}
+
+/// The node that has to be validated again after `node` changed: the
+/// innermost set or `let` around it, or if that is the value of a binding,
+/// the outermost set it can be merged into
+pub(crate) fn scope(node: &SyntaxNode) -> SyntaxNode {
+    let is_binding_set =
+        |node: &SyntaxNode| matches!(node.kind(), NODE_ATTR_SET | NODE_LET_IN | NODE_LEGACY_LET);
+    let Some(mut scope) = node.ancestors().find(is_binding_set) else { return node.clone() };
+    while is_binding_value(&scope) {
+        match scope.ancestors().skip(1).find(is_binding_set) {
+            Some(set) => scope = set,
+            None => break,
+        }
+    }
+    scope
+}
+
+/// Returns true for errors that `validate` produces, as opposed to the parser
+pub(crate) fn is_validation_error(err: &ParseError) -> bool {
+    matches!(err, ParseError::DuplicatedAttr(..) | ParseError::InvalidLiteral(..))
+}
+
+/// Check a number literal the way Nix's lexer reads it. Floats must match
+/// `(([1-9][0-9]*\.[0-9]*)|(0?\.[0-9]+))([Ee][+-]?[0-9]+)?` and fit into an
+/// `f64` without overflowing to infinity or underflowing to zero.
+fn check_literal(kind: SyntaxKind, text: &str) -> Option<LiteralError> {
+    match kind {
+        TOKEN_INTEGER => text.parse::<i64>().is_err().then_some(LiteralError::IntegerOverflow),
+        TOKEN_FLOAT => {
+            let mantissa = text.split(['e', 'E']).next().unwrap_or(text);
+            let (int, fraction) = mantissa.split_once('.')?;
+            if int.len() > 1 && int.starts_with('0') {
+                return Some(LiteralError::FloatLeadingZeros);
+            }
+            if matches!(int, "" | "0") && fraction.is_empty() {
+                return Some(LiteralError::FloatMissingFraction);
+            }
+            let value: f64 = text.parse().ok()?;
+            let zero = mantissa.chars().all(|c| matches!(c, '0' | '.'));
+            (value.is_infinite() || (value == 0.0 && !zero))
+                .then_some(LiteralError::FloatOutOfRange)
+        }
+        _ => None,
+    }
+}
+
+/// The attributes defined so far in a set
+#[derive(Default)]
+struct Defs(HashMap<String, Def>);
+
+struct Def {
+    range: TextRange,
+    /// The attributes of the value, if it's an attribute set that further
+    /// definitions can be merged into
+    attrs: Option<Defs>,
+}
+
+fn check_entries(node: &impl HasEntry, path: &[String], errors: &mut Vec<ParseError>) -> Defs {
+    let mut defs = Defs::default();
+    for entry in node.entries() {
+        match entry {
+            ast::Entry::Inherit(inherit) => {
+                for attr in inherit.attrs() {
+                    let Some(name) = attr.static_name() else { continue };
+                    let range = attr.syntax().text_range();
+                    match defs.0.entry(name) {
+                        MapEntry::Occupied(old) => {
+                            errors.push(duplicated(path, old.key(), range, old.get().range))
+                        }
+                        MapEntry::Vacant(new) => {
+                            new.insert(Def { range, attrs: None });
+                        }
+                    }
+                }
+            }
+            ast::Entry::AttrpathValue(binding) => {
+                check_binding(&mut defs, &binding, path, errors);
+            }
+        }
+    }
+    defs
+}
+
+/// Add the definition of `a.b.c = value` to `defs`, following the same rules
+/// as Nix: the sets implied by `a` and `b` and attribute set values are merged
+/// with earlier definitions, everything else is a duplicate.
+fn check_binding(
+    defs: &mut Defs,
+    binding: &ast::AttrpathValue,
+    path: &[String],
+    errors: &mut Vec<ParseError>,
+) {
+    let value = binding.value().and_then(strip_parens).and_then(ast::AttrSet::cast);
+    let mut path = path.to_vec();
+    let Some((defs, name, range)) = find_parent(defs, binding, &mut path, errors) else {
+        // The value can't be merged with anything, but is still checked on its own
+        if let Some(set) = value {
+            check_entries(&set, &path, errors);
+        }
+        return;
+    };
+
+    let value = value.map(|set| check_entries(&set, &path, errors));
+    match defs.0.entry(name) {
+        MapEntry::Occupied(old) => {
+            let old = old.into_mut();
+            match (&mut old.attrs, value) {
+                (Some(old_attrs), Some(new_attrs)) => {
+                    let mut new_attrs: Vec<_> = new_attrs.0.into_iter().collect();
+                    new_attrs.sort_by_key(|(_, def)| def.range.start());
+                    for (name, def) in new_attrs {
+                        match old_attrs.0.entry(name) {
+                            MapEntry::Occupied(old) => errors.push(duplicated(
+                                &path,
+                                old.key(),
+                                def.range,
+                                old.get().range,
+                            )),
+                            MapEntry::Vacant(new) => {
+                                new.insert(def);
+                            }
+                        }
+                    }
+                }
+                _ => errors.push(ParseError::DuplicatedAttr(range, old.range, path.join("."))),
+            }
+        }
+        MapEntry::Vacant(new) => {
+            new.insert(Def { range, attrs: value });
+        }
+    }
+}
+
+/// Walk the attrpath of a binding up to its last attribute, creating the
+/// implied sets on the way. Returns the set the last attribute is defined in.
+fn find_parent<'d>(
+    mut defs: &'d mut Defs,
+    binding: &ast::AttrpathValue,
+    path: &mut Vec<String>,
+    errors: &mut Vec<ParseError>,
+) -> Option<(&'d mut Defs, String, TextRange)> {
+    let mut attrs = binding.attrpath()?.attrs().peekable();
+    loop {
+        // Dynamic attributes are only known at runtime
+        let attr = attrs.next()?;
+        let name = attr.static_name()?;
+        let range = attr.syntax().text_range();
+        path.push(name.clone());
+
+        if attrs.peek().is_none() {
+            return Some((defs, name, range));
+        }
+        defs = match defs.0.entry(name) {
+            MapEntry::Occupied(old) => {
+                let old = old.into_mut();
+                match &mut old.attrs {
+                    Some(nested) => nested,
+                    None => {
+                        errors.push(ParseError::DuplicatedAttr(range, old.range, path.join(".")));
+                        return None;
+                    }
+                }
+            }
+            MapEntry::Vacant(new) => {
+                new.insert(Def { range, attrs: Some(Defs::default()) }).attrs.as_mut().unwrap()
+            }
+        };
+    }
+}
+
+fn duplicated(path: &[String], name: &str, range: TextRange, old: TextRange) -> ParseError {
+    let mut path = path.join(".");
+    if !path.is_empty() {
+        path.push('.');
+    }
+    path.push_str(name);
+    ParseError::DuplicatedAttr(range, old, path)
+}
+
+fn strip_parens(expr: ast::Expr) -> Option<SyntaxNode> {
+    let mut node = expr.syntax().clone();
+    while let Some(paren) = ast::Paren::cast(node.clone()) {
+        node = paren.expr()?.syntax().clone();
+    }
+    Some(node)
+}
+
+/// Returns true if `node` is an attribute set that is (maybe parenthesized)
+/// the value of a binding
+fn is_binding_value(node: &SyntaxNode) -> bool {
+    if node.kind() != NODE_ATTR_SET {
+        return false;
+    }
+    let mut node = node.clone();
+    while let Some(parent) = node.parent() {
+        match parent.kind() {
+            NODE_PAREN => node = parent,
+            NODE_ATTRPATH_VALUE => return true,
+            _ => return false,
+        }
+    }
+    false
+}
@@ -0,0 +1,11 @@
+[
+  { a = 1; a = 2; }
+  { a.b = 1; a = { b = 2; c = 3; }; a.c = 4; }
+  { a = 1; a.b = 2; x = { y = 1; }; x = rec { y = 2; }; }
+  { "a" = 1; ${"a"} = 2; ${"b${x}"} = 3; ${"b${x}"} = 4; }
+  { a = ({ b = 1; }); a.b.c = 2; }
+  (let x = 1; inherit x; in x)
+  (let { body = 1; body = 2; })
+  { inherit (x) a; a.b = 1; }
+  { ${a}.b = { c = 1; c = 2; }; }
+]
@@ -0,0 +1 @@
+[ ./foo/ 1.0e ~a <a<b> % 1 ]
@@ -0,0 +1,4 @@
+{
+  a = "foo";
+  b = ''
+    bar
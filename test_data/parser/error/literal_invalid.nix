@@ -0,0 +1 @@
+[ 99999999999999999999 9223372036854775807 01.5 0. 1.0e400 1.0e-400 0.0e-400 1. .5e3 ]
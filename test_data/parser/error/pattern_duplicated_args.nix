@@ -0,0 +1 @@
+[ ({ a, b ? 1, a }: a) ({ a }@a: a) (a@{ b, a, ... }: a) ]
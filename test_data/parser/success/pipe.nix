@@ -0,0 +1 @@
+[ (x |> f |> g) (f <| g <| x) (a + 1 |> f b -> c) (f <| a -> b) ]